@@ -1,28 +1,82 @@
 use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::threading::ThreadPool;
+
 pub struct MyAllocator {
     inner: System,
     pub allocations_count: AtomicUsize,
+    /// The total number of bytes that have ever been allocated.
+    pub allocated_bytes: AtomicUsize,
+    /// The number of bytes that are allocated right now.
+    pub live_bytes: AtomicUsize,
+    /// The highest `live_bytes` has ever been.
+    pub peak_bytes: AtomicUsize,
+}
+
+thread_local! {
+    static THREAD_ALLOCATIONS: Cell<AllocStats> = Cell::new(AllocStats { count: 0, bytes: 0 });
+}
+
+impl MyAllocator {
+    fn count(&self, allocated: usize, freed: usize) {
+        self.allocations_count.fetch_add(1, Ordering::Relaxed);
+        self.allocated_bytes.fetch_add(allocated, Ordering::Relaxed);
+        // The change wraps around when more is freed than allocated, which the
+        // wrapping atomic add then subtracts.
+        let change = allocated.wrapping_sub(freed);
+        let live = self
+            .live_bytes
+            .fetch_add(change, Ordering::Relaxed)
+            .wrapping_add(change);
+        self.peak_bytes.fetch_max(live, Ordering::Relaxed);
+
+        // This can fail while the thread is shutting down, those allocations are only
+        // counted globally.
+        let _ = THREAD_ALLOCATIONS.try_with(|stats| {
+            let old = stats.get();
+            stats.set(AllocStats {
+                count: old.count + 1,
+                bytes: old.bytes + allocated,
+            });
+        });
+    }
+
+    /// The allocations made by all the threads so far.
+    pub fn stats(&self) -> AllocStats {
+        AllocStats {
+            count: self.allocations_count.load(Ordering::Relaxed),
+            bytes: self.allocated_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The allocations made by the calling thread so far.
+    pub fn thread_stats(&self) -> AllocStats {
+        THREAD_ALLOCATIONS
+            .try_with(|stats| stats.get())
+            .unwrap_or_default()
+    }
 }
 
 unsafe impl GlobalAlloc for MyAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.allocations_count.fetch_add(1, Ordering::Relaxed);
+        self.count(layout.size(), 0);
         self.inner.alloc(layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.live_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
         self.inner.dealloc(ptr, layout)
     }
 
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        self.allocations_count.fetch_add(1, Ordering::Relaxed);
+        self.count(layout.size(), 0);
         self.inner.alloc_zeroed(layout)
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        self.allocations_count.fetch_add(1, Ordering::Relaxed);
+        self.count(new_size, layout.size());
         self.inner.realloc(ptr, layout, new_size)
     }
 }
@@ -31,4 +85,63 @@ unsafe impl GlobalAlloc for MyAllocator {
 pub static ALLOCATOR: MyAllocator = MyAllocator {
     inner: System,
     allocations_count: AtomicUsize::new(0),
+    allocated_bytes: AtomicUsize::new(0),
+    live_bytes: AtomicUsize::new(0),
+    peak_bytes: AtomicUsize::new(0),
 };
+
+/// A number of allocations, and how many bytes they were for in total.
+/// Reallocations count as allocating the new size.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct AllocStats {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl AllocStats {
+    /// The allocations that happened between `earlier` and `self`.
+    pub fn since(self, earlier: AllocStats) -> AllocStats {
+        AllocStats {
+            count: self.count - earlier.count,
+            bytes: self.bytes - earlier.bytes,
+        }
+    }
+}
+
+impl std::ops::AddAssign for AllocStats {
+    fn add_assign(&mut self, other: AllocStats) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+    }
+}
+
+impl std::fmt::Display for AllocStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} allocations ({} bytes)", self.count, self.bytes)
+    }
+}
+
+/// The allocations made so far by the calling thread and the threads of the pool.
+/// Other threads, like the one playing audio, are left out.
+pub fn pool_stats(thread_pool: &mut ThreadPool) -> AllocStats {
+    let count = AtomicUsize::new(0);
+    let bytes = AtomicUsize::new(0);
+    thread_pool.broadcast(&|_| {
+        let stats = ALLOCATOR.thread_stats();
+        count.fetch_add(stats.count, Ordering::Relaxed);
+        bytes.fetch_add(stats.bytes, Ordering::Relaxed);
+    });
+    AllocStats {
+        count: count.into_inner(),
+        bytes: bytes.into_inner(),
+    }
+}
+
+/// Panics if the calling thread or the threads of the pool allocated since
+/// `before` was taken with `pool_stats`.
+pub fn assert_no_allocations(what: &str, thread_pool: &mut ThreadPool, before: AllocStats) {
+    let allocations = pool_stats(thread_pool).since(before);
+    if allocations.count > 0 {
+        panic!("{} made {} after warming up", what, allocations);
+    }
+}
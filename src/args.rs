@@ -0,0 +1,72 @@
+/// The options the game can be started with from the command line.
+pub struct Args {
+    /// The number of threads used for rendering.
+    pub threads: usize,
+    /// If true, the game panics when rendering or simulating allocates after warming up.
+    pub check_allocs: bool,
+    /// The seed the world is generated from, a new one is picked if it's not given.
+    pub seed: Option<u32>,
+    /// Where the sound is played.
+    pub audio: crate::audio::AudioOutput,
+    /// If set, the room prefabs in this file are checked for problems instead of starting the game.
+    pub validate_prefabs: Option<String>,
+    /// What makes the layout of the world.
+    pub generator: crate::world::generate::GeneratorKind,
+    /// The chance of the generator connecting rooms that are already there, which makes loops.
+    pub loopiness: Option<f32>,
+    /// An image of where rooms can be placed, the dark parts of it are solid rock.
+    pub room_mask: Option<String>,
+    /// If true, rooms are only placed in a circle in the middle of the world.
+    pub round_world: bool,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        let mut args = Args {
+            threads: crate::threading::ThreadPool::default_thread_count(),
+            check_allocs: false,
+            seed: None,
+            audio: crate::audio::AudioOutput::Device,
+            validate_prefabs: None,
+            generator: crate::world::generate::GeneratorKind::Prefabs,
+            loopiness: None,
+            room_mask: None,
+            round_world: false,
+        };
+
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match &*arg {
+                "--threads" => {
+                    args.threads = parse_value(&arg, iter.next())?;
+                    if args.threads == 0 {
+                        return Err("'--threads' has to be at least 1".to_string());
+                    }
+                }
+                "--seed" => args.seed = Some(parse_value(&arg, iter.next())?),
+                "--check-allocs" => args.check_allocs = true,
+                "--generator" => args.generator = parse_value(&arg, iter.next())?,
+                "--loopiness" => args.loopiness = Some(parse_value(&arg, iter.next())?),
+                "--room-mask" => args.room_mask = Some(parse_value(&arg, iter.next())?),
+                "--round-world" => args.round_world = true,
+                "--audio-wav" => {
+                    args.audio = crate::audio::AudioOutput::Wav(parse_value(&arg, iter.next())?)
+                }
+                "--no-audio" => args.audio = crate::audio::AudioOutput::Silent,
+                "--validate-prefabs" => {
+                    args.validate_prefabs = Some(parse_value(&arg, iter.next())?)
+                }
+                _ => return Err(format!("Unknown argument '{}'", arg)),
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("Expected a value after '{}'", name))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for '{}'", value, name))
+}
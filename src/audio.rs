@@ -0,0 +1,220 @@
+mod clip;
+#[cfg(feature = "audio-device")]
+mod device;
+mod mixer;
+mod wav;
+
+use crate::world::TileMap;
+use crate::{Mat2, Vec2};
+use clip::Clip;
+use mixer::{Mixer, Voice};
+use std::sync::{Arc, Mutex};
+
+/// Sounds farther away than this can't be heard.
+const MAX_DISTANCE: f32 = 16.0;
+const MUSIC_VOLUME: f32 = 0.4;
+/// The sample rate used when there is no device to ask.
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+#[derive(Clone, Copy)]
+pub enum Sound {
+    Door,
+    Alert,
+    Footstep,
+}
+
+/// Where the sound should go.
+pub enum AudioOutput {
+    /// The default output device.
+    Device,
+    /// A wav file at the given path.
+    Wav(String),
+    Silent,
+}
+
+enum Backend {
+    #[cfg(feature = "audio-device")]
+    Device(device::DeviceOutput),
+    Wav(wav::WavOutput),
+    Silent,
+}
+
+pub struct Audio {
+    mixer: Arc<Mutex<Mixer>>,
+    backend: Backend,
+    music: Option<Arc<Clip>>,
+    door: Arc<Clip>,
+    alert: Arc<Clip>,
+    footstep: Arc<Clip>,
+    listener_pos: Vec2,
+    listener_matrix: Mat2,
+}
+
+impl Audio {
+    /// Starts the output. If it can't be started the game runs without sound.
+    pub fn new(output: AudioOutput) -> Self {
+        let mixer = Arc::new(Mutex::new(Mixer::new()));
+
+        let backend = match output {
+            #[cfg(feature = "audio-device")]
+            AudioOutput::Device => match device::DeviceOutput::new(mixer.clone()) {
+                Ok(device) => Backend::Device(device),
+                Err(err) => {
+                    eprintln!(
+                        "Couldn't start audio device, running without sound: {}",
+                        err
+                    );
+                    Backend::Silent
+                }
+            },
+            #[cfg(not(feature = "audio-device"))]
+            AudioOutput::Device => {
+                eprintln!("Built without audio device support, running without sound");
+                Backend::Silent
+            }
+            AudioOutput::Wav(path) => match wav::WavOutput::new(&path, DEFAULT_SAMPLE_RATE) {
+                Ok(wav) => Backend::Wav(wav),
+                Err(err) => {
+                    eprintln!("Couldn't create {}, running without sound: {}", path, err);
+                    Backend::Silent
+                }
+            },
+            AudioOutput::Silent => Backend::Silent,
+        };
+
+        let sample_rate = match &backend {
+            #[cfg(feature = "audio-device")]
+            Backend::Device(device) => device.sample_rate(),
+            Backend::Wav(wav) => wav.sample_rate(),
+            Backend::Silent => DEFAULT_SAMPLE_RATE,
+        };
+
+        let music = if let Backend::Silent = backend {
+            None
+        } else {
+            match Clip::load_ogg("assets\\loubi_045287962504587622078796.ogg", sample_rate) {
+                Ok(clip) => Some(Arc::new(clip)),
+                Err(err) => {
+                    eprintln!("Couldn't load music: {}", err);
+                    None
+                }
+            }
+        };
+
+        Self {
+            mixer,
+            backend,
+            music,
+            door: Arc::new(Clip::door(sample_rate)),
+            alert: Arc::new(Clip::alert(sample_rate)),
+            footstep: Arc::new(Clip::footstep(sample_rate)),
+            listener_pos: Vec2::zero(),
+            listener_matrix: Mat2::identity(),
+        }
+    }
+
+    fn is_silent(&self) -> bool {
+        matches!(self.backend, Backend::Silent)
+    }
+
+    /// Starts the background music, which loops forever.
+    pub fn play_music(&mut self) {
+        if let Some(music) = &self.music {
+            let voice = Voice::new(music.clone(), MUSIC_VOLUME, true, None);
+            self.mixer.lock().unwrap().play(voice);
+        }
+    }
+
+    /// Plays a sound coming from somewhere in the world.
+    pub fn play_at(&mut self, sound: Sound, pos: Vec2, tiles: &TileMap) {
+        if self.is_silent() {
+            return;
+        }
+
+        let clip = match sound {
+            Sound::Door => &self.door,
+            Sound::Alert => &self.alert,
+            Sound::Footstep => &self.footstep,
+        };
+        let (gains, lowpass) = spatialize(self.listener_pos, self.listener_matrix, pos, tiles);
+        let voice = Voice::new(clip.clone(), 1.0, false, Some(pos)).with_gains(gains, lowpass);
+        self.mixer.lock().unwrap().play(voice);
+    }
+
+    /// Moves the listener, and when writing to a file, mixes the sound for the time that passed.
+    pub fn update(
+        &mut self,
+        time_step: f32,
+        listener_pos: Vec2,
+        listener_matrix: Mat2,
+        tiles: &TileMap,
+    ) {
+        if self.is_silent() {
+            return;
+        }
+
+        self.listener_pos = listener_pos;
+        self.listener_matrix = listener_matrix;
+
+        let mut mixer = self.mixer.lock().unwrap();
+        for voice in mixer.voices_mut() {
+            if let Some(pos) = voice.pos {
+                let (gains, lowpass) = spatialize(listener_pos, listener_matrix, pos, tiles);
+                voice.set_target(gains, lowpass);
+            }
+        }
+
+        if let Backend::Wav(wav) = &mut self.backend {
+            if let Err(err) = wav.advance(&mut mixer, time_step) {
+                eprintln!("Couldn't write audio, stopping sound: {}", err);
+                drop(mixer);
+                self.backend = Backend::Silent;
+            }
+        }
+    }
+
+    /// Stops the sound, and finishes the wav file if there is one.
+    pub fn finish(self) {
+        if let Backend::Wav(wav) = self.backend {
+            if let Err(err) = wav.finish() {
+                eprintln!("Couldn't finish audio file: {}", err);
+            }
+        }
+    }
+}
+
+/// The volume of the left and right channels and the lowpass for a sound at `pos`.
+/// Sounds get quieter with distance, and quieter and more muffled with every wall in the way.
+fn spatialize(
+    listener_pos: Vec2,
+    listener_matrix: Mat2,
+    pos: Vec2,
+    tiles: &TileMap,
+) -> ([f32; 2], f32) {
+    let to_source = pos - listener_pos;
+    let distance = to_source.magnitude();
+
+    let falloff = (1.0 - distance / MAX_DISTANCE).max(0.0).powi(2);
+    if falloff == 0.0 {
+        return ([0.0, 0.0], 1.0);
+    }
+
+    let walls = tiles.walls_between(listener_pos, pos);
+    let gain = falloff * 0.5f32.powi(walls as i32);
+
+    // Positive x in camera space is the left side of the screen.
+    let local = crate::inverse_mat2(listener_matrix) * to_source;
+    let local_distance = local.magnitude();
+    let pan = if local_distance > 0.0001 {
+        -local.x / local_distance
+    } else {
+        0.0
+    };
+
+    // Equal power panning, so that the sound is as loud in the middle as on the sides.
+    let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+    let gains = [angle.cos() * gain, angle.sin() * gain];
+
+    let lowpass = if walls == 0 { 1.0 } else { 0.15 / walls as f32 };
+    (gains, lowpass)
+}
@@ -0,0 +1,110 @@
+use crate::random::Random;
+use std::f32::consts::TAU;
+
+/// A sound that is ready to be played, as stereo frames at the sample rate of the mixer.
+pub struct Clip {
+    frames: Vec<[f32; 2]>,
+}
+
+impl Clip {
+    /// Decodes an ogg vorbis file, and resamples it to the given sample rate.
+    pub fn load_ogg(path: &str, sample_rate: u32) -> Result<Self, &'static str> {
+        let file = std::fs::File::open(path).map_err(|_| "Couldn't open file")?;
+        let mut reader = lewton::inside_ogg::OggStreamReader::new(std::io::BufReader::new(file))
+            .map_err(|_| "Not an ogg vorbis file")?;
+
+        let channels = reader.ident_hdr.audio_channels as usize;
+        let file_sample_rate = reader.ident_hdr.audio_sample_rate;
+        if channels == 0 {
+            return Err("The file has no channels");
+        }
+
+        let mut frames = Vec::new();
+        while let Some(packet) = reader
+            .read_dec_packet_itl()
+            .map_err(|_| "Couldn't decode ogg vorbis file")?
+        {
+            // Mono files play on both sides, and channels past the first two are skipped.
+            for frame in packet.chunks(channels) {
+                let left = frame[0] as f32 / 32768.0;
+                let right = frame[1.min(channels - 1)] as f32 / 32768.0;
+                frames.push([left, right]);
+            }
+        }
+
+        Ok(Self::resampled(&frames, file_sample_rate, sample_rate))
+    }
+
+    /// Stretches the frames so that they play at the same speed at another sample rate.
+    fn resampled(frames: &[[f32; 2]], from_rate: u32, to_rate: u32) -> Self {
+        if from_rate == to_rate || frames.is_empty() {
+            return Self {
+                frames: frames.to_vec(),
+            };
+        }
+
+        let n_frames = (frames.len() as u64 * to_rate as u64 / from_rate as u64) as usize;
+        let step = from_rate as f32 / to_rate as f32;
+        let frames = (0..n_frames)
+            .map(|i| {
+                let pos = i as f32 * step;
+                let a = frames[(pos as usize).min(frames.len() - 1)];
+                let b = frames[(pos as usize + 1).min(frames.len() - 1)];
+                let t = pos.fract();
+                [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]
+            })
+            .collect();
+        Self { frames }
+    }
+
+    /// Creates a mono clip from a function of the time in seconds.
+    fn generate(sample_rate: u32, length: f32, mut func: impl FnMut(f32) -> f32) -> Self {
+        let n_frames = (length * sample_rate as f32) as usize;
+        let frames = (0..n_frames)
+            .map(|i| {
+                let sample = func(i as f32 / sample_rate as f32);
+                [sample, sample]
+            })
+            .collect();
+        Self { frames }
+    }
+
+    /// A short thump of low noise.
+    pub fn footstep(sample_rate: u32) -> Self {
+        let mut random = Random::with_seed(1);
+        let mut filtered = 0.0;
+        Self::generate(sample_rate, 0.08, |time| {
+            let noise = random.get_float() * 2.0 - 1.0;
+            filtered += (noise - filtered) * 0.1;
+            filtered * (-time * 60.0).exp() * 2.0
+        })
+    }
+
+    /// A low, falling rumble.
+    pub fn door(sample_rate: u32) -> Self {
+        let mut random = Random::with_seed(2);
+        let mut phase = 0.0;
+        Self::generate(sample_rate, 0.5, |time| {
+            phase += (120.0 - time * 100.0) / sample_rate as f32;
+            let noise = random.get_float() * 2.0 - 1.0;
+            ((phase * TAU).sin() * 0.6 + noise * 0.15) * (-time * 6.0).exp()
+        })
+    }
+
+    /// Two rising notes.
+    pub fn alert(sample_rate: u32) -> Self {
+        Self::generate(sample_rate, 0.4, |time| {
+            let frequency = if time < 0.15 { 440.0 } else { 660.0 };
+            let square = if (time * frequency).fract() < 0.5 {
+                1.0
+            } else {
+                -1.0
+            };
+            square * 0.25 * (-(time % 0.15) * 10.0).exp()
+        })
+    }
+
+    pub fn frames(&self) -> &[[f32; 2]] {
+        &self.frames
+    }
+}
@@ -0,0 +1,71 @@
+use super::mixer::Mixer;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::sync::{Arc, Mutex};
+
+/// Plays the mixer through the default output device, on a thread made by cpal.
+pub struct DeviceOutput {
+    // The sound stops when the stream is dropped.
+    _stream: cpal::Stream,
+    sample_rate: u32,
+}
+
+impl DeviceOutput {
+    pub fn new(mixer: Arc<Mutex<Mixer>>) -> Result<Self, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("There is no output device")?;
+        let config = device
+            .default_output_config()
+            .map_err(|err| err.to_string())?;
+
+        let sample_format = config.sample_format();
+        let config: cpal::StreamConfig = config.into();
+        let stream = match sample_format {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, mixer),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, mixer),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, mixer),
+        }
+        .map_err(|err| err.to_string())?;
+        stream.play().map_err(|err| err.to_string())?;
+
+        Ok(Self {
+            _stream: stream,
+            sample_rate: config.sample_rate.0,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+fn build_stream<T: cpal::Sample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mixer: Arc<Mutex<Mixer>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
+    let channels = config.channels as usize;
+    // Mixed into a fixed buffer, so that the audio thread never allocates.
+    let mut frames = [[0.0; 2]; 512];
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut mixer = mixer.lock().unwrap();
+            for chunk in data.chunks_mut(frames.len() * channels) {
+                let frames = &mut frames[..chunk.len() / channels];
+                mixer.mix(frames);
+                for (out, frame) in chunk.chunks_mut(channels).zip(frames.iter()) {
+                    for (channel, sample) in out.iter_mut().enumerate() {
+                        let value = match channels {
+                            1 => (frame[0] + frame[1]) * 0.5,
+                            _ if channel < 2 => frame[channel],
+                            _ => 0.0,
+                        };
+                        *sample = T::from(&value);
+                    }
+                }
+            }
+        },
+        |err| eprintln!("Audio stream error: {}", err),
+    )
+}
@@ -0,0 +1,130 @@
+use super::clip::Clip;
+use crate::Vec2;
+use std::sync::Arc;
+
+/// The most sounds that can play at once, any more than that are skipped.
+const MAX_VOICES: usize = 64;
+
+/// How quickly the volume of a voice follows its target, per frame.
+/// Changing the volume all at once causes clicks.
+const SMOOTHING: f32 = 0.002;
+
+/// A clip that is playing.
+pub struct Voice {
+    clip: Arc<Clip>,
+    /// The next frame of the clip to play.
+    cursor: usize,
+    looping: bool,
+    /// Where the sound is coming from, or None if it's not coming from anywhere in particular.
+    pub pos: Option<Vec2>,
+    volume: f32,
+    /// The volume of the left and right channels.
+    gains: [f32; 2],
+    target_gains: [f32; 2],
+    /// How much of the high frequencies are kept, 1 lets everything through,
+    /// and smaller values muffle the sound more.
+    lowpass: f32,
+    target_lowpass: f32,
+    filtered: [f32; 2],
+}
+
+impl Voice {
+    pub fn new(clip: Arc<Clip>, volume: f32, looping: bool, pos: Option<Vec2>) -> Self {
+        Self {
+            clip,
+            cursor: 0,
+            looping,
+            pos,
+            volume,
+            gains: [1.0, 1.0],
+            target_gains: [1.0, 1.0],
+            lowpass: 1.0,
+            target_lowpass: 1.0,
+            filtered: [0.0, 0.0],
+        }
+    }
+
+    /// Changes how loud the voice is on either side, and how muffled it is.
+    /// The change is smoothed out over a few milliseconds.
+    pub fn set_target(&mut self, gains: [f32; 2], lowpass: f32) {
+        self.target_gains = gains;
+        self.target_lowpass = lowpass;
+    }
+
+    /// Changes the volume at once, for voices that haven't started playing yet.
+    pub fn with_gains(mut self, gains: [f32; 2], lowpass: f32) -> Self {
+        self.set_target(gains, lowpass);
+        self.gains = gains;
+        self.lowpass = lowpass;
+        self
+    }
+
+    /// Adds the voice on top of the frames, and returns false once it's done playing.
+    fn mix_into(&mut self, out: &mut [[f32; 2]]) -> bool {
+        let frames = self.clip.frames();
+        for out in out.iter_mut() {
+            if self.cursor >= frames.len() {
+                if self.looping && !frames.is_empty() {
+                    self.cursor = 0;
+                } else {
+                    return false;
+                }
+            }
+
+            let frame = frames[self.cursor];
+            self.cursor += 1;
+
+            self.lowpass += (self.target_lowpass - self.lowpass) * SMOOTHING;
+            for channel in 0..2 {
+                self.gains[channel] +=
+                    (self.target_gains[channel] - self.gains[channel]) * SMOOTHING;
+                self.filtered[channel] += (frame[channel] - self.filtered[channel]) * self.lowpass;
+                out[channel] += self.filtered[channel] * self.gains[channel] * self.volume;
+            }
+        }
+        true
+    }
+}
+
+pub struct Mixer {
+    voices: Vec<Voice>,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            voices: Vec::with_capacity(MAX_VOICES),
+        }
+    }
+
+    pub fn play(&mut self, voice: Voice) {
+        if self.voices.len() < MAX_VOICES {
+            self.voices.push(voice);
+        }
+    }
+
+    pub fn voices_mut(&mut self) -> impl Iterator<Item = &mut Voice> {
+        self.voices.iter_mut()
+    }
+
+    /// Fills the frames with all the voices mixed together.
+    pub fn mix(&mut self, out: &mut [[f32; 2]]) {
+        for frame in out.iter_mut() {
+            *frame = [0.0, 0.0];
+        }
+
+        let mut i = 0;
+        while i < self.voices.len() {
+            if self.voices[i].mix_into(out) {
+                i += 1;
+            } else {
+                self.voices.swap_remove(i);
+            }
+        }
+
+        for frame in out.iter_mut() {
+            frame[0] = frame[0].clamp(-1.0, 1.0);
+            frame[1] = frame[1].clamp(-1.0, 1.0);
+        }
+    }
+}
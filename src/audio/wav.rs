@@ -0,0 +1,79 @@
+use super::mixer::Mixer;
+use std::io::{Seek, SeekFrom, Write};
+
+/// Mixes the sound as the game runs and writes it to a 16 bit stereo wav file,
+/// for when there is no sound device.
+pub struct WavOutput {
+    file: std::io::BufWriter<std::fs::File>,
+    sample_rate: u32,
+    n_frames: u32,
+    /// The part of a frame that was left over at the end of the last update.
+    frames_behind: f32,
+    buffer: Vec<[f32; 2]>,
+}
+
+impl WavOutput {
+    pub fn new(path: &str, sample_rate: u32) -> std::io::Result<Self> {
+        let mut output = Self {
+            file: std::io::BufWriter::new(std::fs::File::create(path)?),
+            sample_rate,
+            n_frames: 0,
+            frames_behind: 0.0,
+            buffer: Vec::new(),
+        };
+        // The sizes in the header are filled in once all the frames have been written.
+        output.write_header()?;
+        Ok(output)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Mixes as many frames as it takes to play for the given time.
+    pub fn advance(&mut self, mixer: &mut Mixer, time: f32) -> std::io::Result<()> {
+        self.frames_behind += time * self.sample_rate as f32;
+        let n_frames = self.frames_behind as usize;
+        self.frames_behind -= n_frames as f32;
+
+        self.buffer.resize(n_frames, [0.0, 0.0]);
+        mixer.mix(&mut self.buffer);
+        for frame in self.buffer.iter() {
+            for &sample in frame {
+                self.file
+                    .write_all(&((sample * i16::MAX as f32) as i16).to_le_bytes())?;
+            }
+        }
+        self.n_frames += n_frames as u32;
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header, without this the file can't be played.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        const CHANNELS: u16 = 2;
+        const BYTES_PER_SAMPLE: u16 = 2;
+        let data_size = self.n_frames * (CHANNELS * BYTES_PER_SAMPLE) as u32;
+
+        let file = &mut self.file;
+        file.write_all(b"RIFF")?;
+        file.write_all(&(36 + data_size).to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // Uncompressed pcm.
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&CHANNELS.to_le_bytes())?;
+        file.write_all(&self.sample_rate.to_le_bytes())?;
+        file.write_all(&(self.sample_rate * (CHANNELS * BYTES_PER_SAMPLE) as u32).to_le_bytes())?;
+        file.write_all(&(CHANNELS * BYTES_PER_SAMPLE).to_le_bytes())?;
+        file.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&data_size.to_le_bytes())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+/// A fixed number of bits, packed 64 to a word.
+#[derive(Clone, Default)]
+pub struct BitSet {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitSet {
+    /// Creates a bitset where all the bits are unset.
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; (len + 63) / 64],
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Changes the number of bits, and unsets all of them.
+    pub fn reset(&mut self, len: usize) {
+        self.words.clear();
+        self.words.resize((len + 63) / 64, 0);
+        self.len = len;
+    }
+
+    /// Unsets all the bits.
+    pub fn clear(&mut self) {
+        for word in self.words.iter_mut() {
+            *word = 0;
+        }
+    }
+
+    #[inline]
+    pub fn insert(&mut self, index: usize) {
+        assert!(index < self.len);
+        self.words[index / 64] |= 1 << (index % 64);
+    }
+
+    #[inline]
+    pub fn contains(&self, index: usize) -> bool {
+        index < self.len && self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Sets all the bits that are set in `other`. Both sets have to be the same length.
+    pub fn union_with(&mut self, other: &BitSet) {
+        assert_eq!(self.len, other.len);
+        for (word, other) in self.words.iter_mut().zip(other.words.iter()) {
+            *word |= other;
+        }
+    }
+
+    /// The number of bits that are set.
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// The bits packed into bytes, with the lowest bit of the first byte being the first bit.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self
+            .words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        bytes.truncate((self.len + 7) / 8);
+        bytes
+    }
+
+    /// The opposite of `to_bytes`, returns None if there aren't exactly enough bytes.
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Option<Self> {
+        if bytes.len() != (len + 7) / 8 {
+            return None;
+        }
+
+        let mut set = Self::new(len);
+        for (word, chunk) in set.words.iter_mut().zip(bytes.chunks(8)) {
+            let mut word_bytes = [0; 8];
+            word_bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_le_bytes(word_bytes);
+        }
+        // Bits past the end could be set in the last byte, they're not part of the set.
+        if let Some(last) = set.words.last_mut() {
+            if len % 64 != 0 {
+                *last &= (1 << (len % 64)) - 1;
+            }
+        }
+        Some(set)
+    }
+}
@@ -0,0 +1,50 @@
+use crate::overlay::Overlay;
+use crate::world::Health;
+use crate::Vec2;
+
+const TEXT_COLOR: u32 = 0xffffff;
+const HEALTH_COLOR: u32 = 0xc02020;
+
+/// Everything the HUD shows, collected once per frame.
+pub struct HudInfo {
+    pub fps: f32,
+    pub seed: u32,
+    pub player_pos: Vec2,
+    pub player_health: Option<Health>,
+}
+
+/// Draws the health of the player at the bottom of the screen, and if `debug` is true,
+/// some information about the game in the top left corner.
+pub fn draw_hud(overlay: &mut Overlay, info: &HudInfo, debug: bool) {
+    if let Some(health) = info.player_health {
+        let width = overlay.width() / 4;
+        let height = overlay.line_height();
+        let x = (overlay.width() - width) / 2;
+        let y = overlay.height().saturating_sub(height * 2);
+        overlay.bar(
+            x,
+            y,
+            width,
+            height,
+            health.current / health.max,
+            HEALTH_COLOR,
+        );
+        let margin = (height - overlay.text_height()) / 2;
+        overlay.text(
+            x + margin,
+            y + margin,
+            TEXT_COLOR,
+            format_args!("{:.0}/{:.0}", health.current, health.max),
+        );
+    }
+
+    if debug {
+        let mut lines = overlay.lines(0, 0);
+        lines.line(TEXT_COLOR, format_args!("FPS {:.0}", info.fps));
+        lines.line(TEXT_COLOR, format_args!("Seed {}", info.seed));
+        lines.line(
+            TEXT_COLOR,
+            format_args!("Pos {:.1} {:.1}", info.player_pos.x, info.player_pos.y),
+        );
+    }
+}
@@ -1,11 +1,18 @@
 #![feature(array_methods)]
 #![feature(clamp)]
 
-use minifb::{Key, Window, WindowOptions};
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
 #[macro_use]
 mod id;
 mod alloc;
+mod args;
+mod audio;
+mod bitset;
 mod float_range;
+mod hud;
+mod map;
+mod overlay;
+mod profiler;
 mod random;
 mod raycast;
 mod render;
@@ -16,18 +23,80 @@ mod world;
 type Vec2 = vek::vec::repr_simd::Vec2<f32>;
 type Mat2 = vek::mat::repr_simd::column_major::Mat2<f32>;
 
+/// How many frames to wait before checking for allocations with `--check-allocs`.
+/// This is longer than the profiler history, so that the buffers of every frame
+/// in it have grown to their full size.
+const ALLOC_WARM_UP_FRAMES: usize = 200;
+
+const PREFABS_PATH: &str = "assets\\room_prefabs.txt";
+const DECORATIONS_PATH: &str = "assets\\decorations.txt";
+/// The width and height of the world, in rooms.
+const WORLD_SIZE: usize = 100;
+
 fn main() {
+    let args = match args::Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("Usage: raycaster [--threads <count>] [--seed <seed>] [--check-allocs] [--generator <prefabs|caves|bsp|any>] [--loopiness <chance>] [--room-mask <path> | --round-world] [--audio-wav <path> | --no-audio] [--validate-prefabs <path>]");
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(path) = &args.validate_prefabs {
+        std::process::exit(validate_prefabs(path));
+    }
+
     let textures = texture::Textures::new().unwrap();
 
     let mut buffer: Vec<u32> = Vec::new();
 
-    let mut random = random::Random::new();
-    let (player_id, mut world) =
-        world::generate::WorldGenerator::new(100, 100, "assets\\room_prefabs.txt")
-            .unwrap()
-            .generate(&mut random, Vec2::one() * 102.5);
+    let seed = args.seed.unwrap_or_else(random::Random::time_seed);
+    println!("Generating world with seed {}", seed);
+    let mut random = random::Random::with_seed(seed);
+    let generator = level_generator(&args, &mut random);
+    let level = generator.generate_level(&mut random, Vec2::one() * 102.5);
+
+    if let Some(room_graph) = &level.rooms {
+        if let Err(err) = room_graph.save_dot("debug_rooms.dot") {
+            eprintln!("Couldn't save debug_rooms.dot: {}", err);
+        }
+        let farthest = &room_graph.rooms[room_graph.farthest_room()];
+        println!(
+            "The farthest room is '{}' at ({}, {}), {} rooms from the start",
+            farthest.name,
+            farthest.x,
+            farthest.y,
+            farthest.distance.unwrap_or(0)
+        );
+    }
+    let decorations =
+        world::generate::Decorations::load(DECORATIONS_PATH).unwrap_or_else(|errors| {
+            for error in &errors {
+                eprintln!("{}", error);
+            }
+            eprintln!("Couldn't load the decorations, {} errors", errors.len());
+            std::process::exit(1);
+        });
+    let (player_id, mut world) = level.into_world(&mut random, &decorations);
+
+    world.tiles.to_image("debug_maze.png", None);
 
-    world.tiles.to_image("debug_maze.png");
+    // The explored tiles are saved next to the game, for every seed. Other generators
+    // and options make other maps with the same seed, which the layout tells apart.
+    let explored_path = format!("explored_{}.bin", seed);
+    let layout = world.tiles.layout_hash();
+    if std::path::Path::new(&explored_path).exists() {
+        match world::ExploredTiles::load(
+            &explored_path,
+            world.tiles.width(),
+            world.tiles.height(),
+            layout,
+        ) {
+            Ok(explored) => world.explored = explored,
+            Err(err) => eprintln!("Couldn't load {}: {}", explored_path, err),
+        }
+    }
 
     let mut window = Window::new(
         "Raycaster",
@@ -53,16 +122,79 @@ fn main() {
 
     let mut elapsed_time = 0.0;
 
-    let mut thread_pool = threading::ThreadPool::new(4);
+    let mut render_settings = render::RenderSettings::default();
+
+    let mut profiler = profiler::Profiler::new();
+    let mut show_profiler = false;
+    let mut show_debug_hud = true;
+    let mut show_automap = false;
+    let mut fps = 0.0;
+
+    let mut thread_pool = threading::ThreadPool::new(args.threads);
+    let mut renderer = render::Renderer::new();
+
+    let mut audio = audio::Audio::new(args.audio);
+    audio.play_music();
+
+    let mut frame_allocations = alloc::AllocStats::default();
+    let mut main_thread_allocations = alloc::AllocStats::default();
+    let mut frames_since_resize = 0;
+    let mut last_size = (0, 0);
     while window.is_open() && !window.is_key_down(Key::F4) {
         let (width, height) = window.get_size();
         let aspect = height as f32 / width as f32;
 
         buffer.resize(width * height, 0);
 
+        // Resizing the window makes all the buffers grow, so the warm up starts over.
+        if (width, height) != last_size {
+            last_size = (width, height);
+            frames_since_resize = 0;
+        }
+        let check_allocs = args.check_allocs && frames_since_resize >= ALLOC_WARM_UP_FRAMES;
+        frames_since_resize += 1;
+
         let instant = std::time::Instant::now();
+        let frame_start_allocations = alloc::ALLOCATOR.stats();
+        let frame_start_thread_allocations = alloc::ALLOCATOR.thread_stats();
+        profiler.begin_frame();
+
+        let input_scope = profiler.scope("input");
+        if window.is_key_pressed(Key::F1, KeyRepeat::No) {
+            render_settings.mip_maps = !render_settings.mip_maps;
+            println!("Mip maps: {}", render_settings.mip_maps);
+        }
+        if window.is_key_pressed(Key::F2, KeyRepeat::No) {
+            render_settings.filter = match render_settings.filter {
+                render::TextureFilter::Nearest => render::TextureFilter::Bilinear,
+                render::TextureFilter::Bilinear => render::TextureFilter::Nearest,
+            };
+            println!("Texture filter: {:?}", render_settings.filter);
+        }
+        if window.is_key_pressed(Key::F3, KeyRepeat::No) {
+            render_settings.min_split_size = match render_settings.min_split_size {
+                64 => 1,
+                n => n * 2,
+            };
+            println!("Min split size: {}", render_settings.min_split_size);
+        }
+        if window.is_key_pressed(Key::F5, KeyRepeat::No) {
+            show_profiler = !show_profiler;
+        }
+        if window.is_key_pressed(Key::F6, KeyRepeat::No) {
+            match profiler.write_chrome_trace("trace.json") {
+                Ok(()) => println!("Wrote profiling data to trace.json"),
+                Err(err) => println!("Couldn't write profiling data: {}", err),
+            }
+        }
+        if window.is_key_pressed(Key::F7, KeyRepeat::No) {
+            show_debug_hud = !show_debug_hud;
+        }
+        if window.is_key_pressed(Key::M, KeyRepeat::No) {
+            show_automap = !show_automap;
+        }
 
-        if let Some(player) = world.entities.transforms.get_mut(&player_id) {
+        if let Some(player) = world.entities.transforms.get_mut(player_id) {
             if window.is_key_down(Key::Right) {
                 player.rot += 5.0 * last_frame_time;
             }
@@ -86,33 +218,94 @@ fn main() {
                 player.vel += cam_matrix * Vec2::down() * player_speed;
             }
         }
+        std::mem::drop(input_scope);
 
+        let simulate_scope = profiler.scope("simulate");
+        let before = if check_allocs {
+            Some(alloc::pool_stats(&mut thread_pool))
+        } else {
+            None
+        };
         world.simulate(last_frame_time, elapsed_time);
+        if let Some(before) = before {
+            alloc::assert_no_allocations("World::simulate", &mut thread_pool, before);
+        }
+        std::mem::drop(simulate_scope);
 
-        if let Some(player) = world.entities.transforms.get(&player_id) {
+        if let Some(player) = world.entities.transforms.get(player_id) {
             cam_pos = player.pos;
         }
 
-        for val in buffer.iter_mut() {
-            *val = 0;
+        let audio_scope = profiler.scope("audio");
+        for event in world.sound_events.drain(..) {
+            audio.play_at(event.sound, event.pos, &world.tiles);
         }
-        thread_pool.raycast_scene(
+        audio.update(last_frame_time, cam_pos, cam_matrix, &world.tiles);
+        std::mem::drop(audio_scope);
+
+        let render_scope = profiler.scope("render");
+        let before = if check_allocs {
+            Some(alloc::pool_stats(&mut thread_pool))
+        } else {
+            None
+        };
+        let camera = render::Camera {
+            pos: cam_pos,
+            matrix: cam_matrix,
+            aspect,
+        };
+        renderer.raycast_scene(
+            &mut thread_pool,
             &world,
             &textures,
-            cam_pos,
-            cam_matrix,
-            width,
-            height,
-            &mut buffer,
-            aspect,
-            elapsed_time,
+            render::Frame {
+                camera,
+                buffer: &mut buffer,
+                width,
+                height,
+                world_time: elapsed_time,
+                settings: render_settings,
+            },
+            &profiler,
         );
+        if let Some(before) = before {
+            alloc::assert_no_allocations("Renderer::raycast_scene", &mut thread_pool, before);
+        }
+        renderer.mark_explored(&mut world.explored);
+
+        let hud_info = hud::HudInfo {
+            fps,
+            seed,
+            player_pos: cam_pos,
+            player_health: world.entities.healths.get(player_id).copied(),
+        };
+        let mut overlay = overlay::Overlay::new(&mut buffer, width, height);
+        if show_automap {
+            map::draw_automap(&mut overlay, &world, &camera);
+        } else {
+            map::draw_minimap(&mut overlay, &world, &camera);
+        }
+        hud::draw_hud(&mut overlay, &hud_info, show_debug_hud);
+        if show_profiler {
+            profiler.draw_graph(&mut buffer, width, height);
+        }
+        std::mem::drop(render_scope);
 
         frame_rate[frame_rate_index] = instant.elapsed().as_secs_f32();
 
+        let present_scope = profiler.scope("present");
         window.update_with_buffer(&buffer, width, height).unwrap();
+        std::mem::drop(present_scope);
+        profiler.end_frame();
+
+        frame_allocations += alloc::ALLOCATOR.stats().since(frame_start_allocations);
+        main_thread_allocations += alloc::ALLOCATOR
+            .thread_stats()
+            .since(frame_start_thread_allocations);
 
         last_frame_time = instant.elapsed().as_secs_f32();
+        // Smoothed out a bit, so that it's possible to read.
+        fps += (1.0 / last_frame_time - fps) * 0.1;
         elapsed_time += last_frame_time;
         frame_rate_index += 1;
         if frame_rate_index >= frame_rate.len() {
@@ -123,16 +316,123 @@ fn main() {
                 average,
                 1.0 / average
             );
+            println!("{}", renderer.stats());
+            let n_frames = frame_rate.len();
+            println!(
+                "{} / {} allocations per frame (all threads / main thread)",
+                frame_allocations.count as f32 / n_frames as f32,
+                main_thread_allocations.count as f32 / n_frames as f32,
+            );
             println!(
-                "{} allocations",
-                crate::alloc::ALLOCATOR
-                    .allocations_count
-                    .load(std::sync::atomic::Ordering::Relaxed)
+                "{} bytes allocated per frame, {} bytes in use, peak {} bytes",
+                frame_allocations.bytes / n_frames,
+                alloc::ALLOCATOR
+                    .live_bytes
+                    .load(std::sync::atomic::Ordering::Relaxed),
+                alloc::ALLOCATOR
+                    .peak_bytes
+                    .load(std::sync::atomic::Ordering::Relaxed),
             );
+            frame_allocations = alloc::AllocStats::default();
+            main_thread_allocations = alloc::AllocStats::default();
         }
     }
 
     thread_pool.join();
+    audio.finish();
+
+    println!(
+        "Explored {} of {} tiles",
+        world.explored.n_explored(),
+        world.explored.n_tiles()
+    );
+    if let Err(err) = world.explored.save(&explored_path, layout) {
+        eprintln!("Couldn't save {}: {}", explored_path, err);
+    }
+    world
+        .tiles
+        .to_image("debug_explored.png", Some(&world.explored));
+}
+
+/// Makes the generator picked on the command line.
+fn level_generator(
+    args: &args::Args,
+    random: &mut random::Random,
+) -> Box<dyn world::generate::LevelGenerator> {
+    use world::generate::{BspGenerator, CaveGenerator, GeneratorKind, ROOM_HEIGHT, ROOM_WIDTH};
+
+    let kind = match args.generator {
+        GeneratorKind::Any => match random.get_32() % 3 {
+            0 => GeneratorKind::Prefabs,
+            1 => GeneratorKind::Caves,
+            _ => GeneratorKind::Bsp,
+        },
+        kind => kind,
+    };
+    let (width, height) = (WORLD_SIZE * ROOM_WIDTH, WORLD_SIZE * ROOM_HEIGHT);
+
+    match kind {
+        GeneratorKind::Caves => Box::new(CaveGenerator::new(width, height)),
+        GeneratorKind::Bsp => Box::new(BspGenerator::new(width, height)),
+        _ => {
+            let mut generator = load_world_generator(PREFABS_PATH);
+            if let Some(loopiness) = args.loopiness {
+                generator = generator.with_loopiness(loopiness);
+            }
+            if let Some(path) = &args.room_mask {
+                generator = generator.with_mask_image(path).unwrap_or_else(|err| {
+                    eprintln!("{}: {}", path, err);
+                    std::process::exit(1);
+                });
+            } else if args.round_world {
+                let radius = WORLD_SIZE as f32 / 2.0;
+                generator = generator.with_mask(|x, y| {
+                    let offset = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - Vec2::one() * radius;
+                    offset.magnitude() <= radius
+                });
+            }
+            Box::new(generator)
+        }
+    }
+}
+
+/// Loads the room prefabs, and exits if there are problems with them.
+fn load_world_generator(path: &str) -> world::generate::WorldGenerator {
+    match world::generate::WorldGenerator::new(WORLD_SIZE, WORLD_SIZE, path) {
+        Ok(generator) => generator,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("{}", error);
+            }
+            eprintln!("Couldn't load the room prefabs, {} errors", errors.len());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Checks the room prefabs for problems and saves pictures of them.
+/// Returns the exit code, which is 1 if there were problems.
+fn validate_prefabs(path: &str) -> i32 {
+    let generator = load_world_generator(path);
+
+    let errors = generator.validate_prefabs();
+    for error in &errors {
+        eprintln!("{}", error);
+    }
+
+    if let Err(err) = generator.save_prefab_images("debug_prefabs") {
+        eprintln!("Couldn't save prefab images: {}", err);
+        return 1;
+    }
+    println!("Saved prefab images to debug_prefabs");
+
+    if errors.is_empty() {
+        println!("No problems found in {}", path);
+        0
+    } else {
+        eprintln!("Found {} problems in {}", errors.len(), path);
+        1
+    }
 }
 
 pub fn inverse_mat2(mat: Mat2) -> Mat2 {
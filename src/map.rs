@@ -0,0 +1,152 @@
+use crate::overlay::Overlay;
+use crate::render::Camera;
+use crate::world::World;
+use crate::Vec2;
+
+const PLAYER_COLOR: u32 = 0x2080ff;
+const VIEW_CONE_COLOR: u32 = 0xffff00;
+const ENEMY_COLOR: u32 = 0xff2020;
+const ENTITY_COLOR: u32 = 0x20ff20;
+
+/// A part of the screen that shows the map, centered on the camera.
+struct MapArea<'c> {
+    camera: &'c Camera,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    /// How many pixels wide and tall every tile is.
+    tile_size: f32,
+}
+
+impl MapArea<'_> {
+    fn to_screen(&self, pos: Vec2) -> Vec2 {
+        Vec2::new(
+            (self.x + self.width / 2) as f32,
+            (self.y + self.height / 2) as f32,
+        ) + (pos - self.camera.pos) * self.tile_size
+    }
+
+    fn contains(&self, pos: Vec2) -> bool {
+        pos.x >= self.x as f32
+            && pos.y >= self.y as f32
+            && pos.x < (self.x + self.width) as f32
+            && pos.y < (self.y + self.height) as f32
+    }
+
+    /// Draws all the tiles in the area that `is_visible` returns true for.
+    fn draw_tiles(
+        &self,
+        overlay: &mut Overlay,
+        world: &World,
+        is_visible: impl Fn(isize, isize) -> bool,
+    ) {
+        let half_size = Vec2::new(self.width as f32, self.height as f32) / (2.0 * self.tile_size);
+        let min = self.camera.pos - half_size;
+        let max = self.camera.pos + half_size;
+
+        for y in min.y.floor() as isize..=max.y.floor() as isize {
+            for x in min.x.floor() as isize..=max.x.floor() as isize {
+                let tile = match world.tiles.get(x, y) {
+                    Some(tile) if is_visible(x, y) => tile,
+                    _ => continue,
+                };
+
+                // Tiles on the edge of the area are cut off.
+                let start = self.to_screen(Vec2::new(x as f32, y as f32));
+                let end = start + Vec2::one() * self.tile_size;
+                let start_x = (start.x.max(self.x as f32)) as usize;
+                let start_y = (start.y.max(self.y as f32)) as usize;
+                let end_x = (end.x.min((self.x + self.width) as f32)) as usize;
+                let end_y = (end.y.min((self.y + self.height) as f32)) as usize;
+                if end_x <= start_x || end_y <= start_y {
+                    continue;
+                }
+
+                let [r, g, b] = tile.kind().map_color();
+                overlay.fill_rect(
+                    start_x,
+                    start_y,
+                    end_x - start_x,
+                    end_y - start_y,
+                    (r as u32) << 16 | (g as u32) << 8 | b as u32,
+                );
+            }
+        }
+    }
+
+    /// Draws a dot where the player is, and lines along the edges of what they can see.
+    fn draw_camera(&self, overlay: &mut Overlay) {
+        let center = self.to_screen(self.camera.pos);
+        let length = self.tile_size * 4.0;
+        for &side in &[-1.0, 1.0] {
+            let direction = self.camera.matrix * Vec2::new(side * 0.5 / self.camera.aspect, 1.0);
+            let end = center + direction.normalized() * length;
+            overlay.line(center, end, VIEW_CONE_COLOR);
+        }
+
+        self.draw_dot(overlay, self.camera.pos, PLAYER_COLOR);
+    }
+
+    fn draw_dot(&self, overlay: &mut Overlay, pos: Vec2, color: u32) {
+        let pos = self.to_screen(pos);
+        if self.contains(pos) {
+            let size = overlay.scale() * 2;
+            overlay.fill_rect(
+                (pos.x as usize).saturating_sub(size / 2),
+                (pos.y as usize).saturating_sub(size / 2),
+                size,
+                size,
+                color,
+            );
+        }
+    }
+}
+
+/// Draws a small map of everything around the player in the top right corner of the screen,
+/// with all the entities that are close by.
+pub fn draw_minimap(overlay: &mut Overlay, world: &World, camera: &Camera) {
+    let size = overlay.height() / 3;
+    let margin = overlay.scale() * 2;
+    if overlay.width() < size + margin {
+        return;
+    }
+
+    let area = MapArea {
+        camera,
+        x: overlay.width() - size - margin,
+        y: margin,
+        width: size,
+        height: size,
+        tile_size: overlay.scale() as f32 * 3.0,
+    };
+
+    overlay.darken_rect(area.x, area.y, area.width, area.height);
+    area.draw_tiles(overlay, world, |_, _| true);
+
+    for (entity_id, transform) in world.entities.transforms.iter() {
+        let color = match world.entities.evil_enemies.contains(entity_id) {
+            true => ENEMY_COLOR,
+            false => ENTITY_COLOR,
+        };
+        area.draw_dot(overlay, transform.pos, color);
+    }
+
+    area.draw_camera(overlay);
+}
+
+/// Draws a map over the entire screen, which only shows the tiles the player has seen.
+pub fn draw_automap(overlay: &mut Overlay, world: &World, camera: &Camera) {
+    let area = MapArea {
+        camera,
+        x: 0,
+        y: 0,
+        width: overlay.width(),
+        height: overlay.height(),
+        tile_size: overlay.scale() as f32 * 2.0,
+    };
+
+    overlay.darken_rect(area.x, area.y, area.width, area.height);
+    area.draw_tiles(overlay, world, |x, y| world.explored.is_explored(x, y));
+    area.draw_camera(overlay);
+}
@@ -0,0 +1,196 @@
+mod font;
+
+use crate::Vec2;
+use font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use std::fmt::{self, Write};
+
+/// Draws text and simple shapes on top of a frame that has already been rendered.
+///
+/// Nothing is kept between frames, a new overlay is made for every frame and everything
+/// is drawn straight into the buffer. Everything that's drawn is clipped to the buffer,
+/// and text is formatted as it's drawn, so that it doesn't have to allocate.
+pub struct Overlay<'a> {
+    buffer: &'a mut [u32],
+    width: usize,
+    height: usize,
+    /// How many screen pixels every pixel of the font takes up.
+    scale: usize,
+}
+
+impl<'a> Overlay<'a> {
+    /// Creates an overlay, where the text gets bigger the bigger the buffer is.
+    pub fn new(buffer: &'a mut [u32], width: usize, height: usize) -> Self {
+        assert_eq!(width * height, buffer.len());
+        Self {
+            buffer,
+            width,
+            height,
+            scale: (height / 240).max(1),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// How many screen pixels every pixel of the font takes up.
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    /// The height of the text itself.
+    pub fn text_height(&self) -> usize {
+        GLYPH_HEIGHT * self.scale
+    }
+
+    /// The height of a line of text, including the space between lines.
+    pub fn line_height(&self) -> usize {
+        (GLYPH_HEIGHT + 2) * self.scale
+    }
+
+    /// The width of every character of text, including the space between characters.
+    pub fn char_width(&self) -> usize {
+        (GLYPH_WIDTH + 1) * self.scale
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        self.for_each_pixel(x, y, width, height, |pixel| *pixel = color);
+    }
+
+    /// Sets a single pixel, if it's on the screen.
+    pub fn set_pixel(&mut self, x: isize, y: isize, color: u32) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            self.buffer[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    /// Draws a line that is one pixel wide, the parts outside of the screen are skipped.
+    pub fn line(&mut self, from: Vec2, to: Vec2, color: u32) {
+        let n_steps = (to.x - from.x).abs().max((to.y - from.y).abs()).ceil() as usize;
+        for i in 0..=n_steps {
+            let pos = from + (to - from) * (i as f32 / n_steps.max(1) as f32);
+            self.set_pixel(pos.x.floor() as isize, pos.y.floor() as isize, color);
+        }
+    }
+
+    /// Makes a part of the screen darker, so that text on top of it is easy to read.
+    pub fn darken_rect(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        self.for_each_pixel(x, y, width, height, |pixel| {
+            *pixel = (*pixel >> 2) & 0x3f3f3f
+        });
+    }
+
+    /// Draws a bar that is filled up from the left, where a fill of 1 is completely full.
+    pub fn bar(&mut self, x: usize, y: usize, width: usize, height: usize, fill: f32, color: u32) {
+        let filled = (width as f32 * fill.clamp(0.0, 1.0)) as usize;
+        self.fill_rect(x, y, filled, height, color);
+        self.darken_rect(x + filled, y, width - filled, height);
+    }
+
+    /// Draws formatted text with its top left corner at the given position,
+    /// and returns the x position right after the text.
+    pub fn text(&mut self, x: usize, y: usize, color: u32, args: fmt::Arguments) -> usize {
+        let mut writer = TextWriter {
+            overlay: self,
+            x,
+            y,
+            color,
+        };
+        // Writing to the screen can't fail.
+        let _ = writer.write_fmt(args);
+        writer.x
+    }
+
+    /// How wide the text would be if it was drawn.
+    pub fn text_width(&self, args: fmt::Arguments) -> usize {
+        let mut counter = CharCounter(0);
+        let _ = counter.write_fmt(args);
+        counter.0 * self.char_width()
+    }
+
+    /// Starts a list of lines of text, with its top left corner at the given position.
+    pub fn lines(&mut self, x: usize, y: usize) -> Lines<'_, 'a> {
+        Lines {
+            overlay: self,
+            x,
+            y,
+        }
+    }
+
+    fn draw_char(&mut self, x: usize, y: usize, c: char, color: u32) {
+        let scale = self.scale;
+        for (row_y, row) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    self.fill_rect(x + column * scale, y + row_y * scale, scale, scale, color);
+                }
+            }
+        }
+    }
+
+    fn for_each_pixel(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        mut func: impl FnMut(&mut u32),
+    ) {
+        let x_end = (x + width).min(self.width);
+        let y_end = (y + height).min(self.height);
+        for y in y.min(y_end)..y_end {
+            for pixel in &mut self.buffer[y * self.width + x.min(x_end)..y * self.width + x_end] {
+                func(pixel);
+            }
+        }
+    }
+}
+
+/// A list of lines of text, every one with a dark background behind it.
+pub struct Lines<'o, 'a> {
+    overlay: &'o mut Overlay<'a>,
+    x: usize,
+    y: usize,
+}
+
+impl Lines<'_, '_> {
+    pub fn line(&mut self, color: u32, args: fmt::Arguments) {
+        let scale = self.overlay.scale;
+        let width = self.overlay.text_width(args) + scale;
+        let height = self.overlay.line_height();
+        self.overlay.darken_rect(self.x, self.y, width, height);
+        self.overlay
+            .text(self.x + scale, self.y + scale, color, args);
+        self.y += height;
+    }
+}
+
+struct TextWriter<'o, 'a> {
+    overlay: &'o mut Overlay<'a>,
+    x: usize,
+    y: usize,
+    color: u32,
+}
+
+impl Write for TextWriter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.overlay.draw_char(self.x, self.y, c, self.color);
+            self.x += self.overlay.char_width();
+        }
+        Ok(())
+    }
+}
+
+struct CharCounter(usize);
+
+impl Write for CharCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.chars().count();
+        Ok(())
+    }
+}
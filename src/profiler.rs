@@ -0,0 +1,186 @@
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many frames of timings are kept around.
+const HISTORY: usize = 128;
+
+/// The time of a single named scope, relative to when the profiler was created.
+#[derive(Clone, Copy)]
+pub struct ScopeTiming {
+    pub name: &'static str,
+    pub thread_index: usize,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+#[derive(Default)]
+pub struct FrameTimings {
+    pub start: Duration,
+    pub end: Duration,
+    pub scopes: Vec<ScopeTiming>,
+}
+
+/// Keeps track of how long different parts of every frame take.
+///
+/// Scopes can be timed from any thread, they are collected into the frame
+/// that is in progress, and moved into a ring buffer once the frame ends.
+pub struct Profiler {
+    created: Instant,
+    current: Mutex<FrameTimings>,
+    frames: Vec<FrameTimings>,
+    /// Where the next frame goes in `frames`.
+    next_frame: usize,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            created: Instant::now(),
+            current: Mutex::new(FrameTimings::default()),
+            frames: (0..HISTORY).map(|_| FrameTimings::default()).collect(),
+            next_frame: 0,
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        let now = self.created.elapsed();
+        let current = self.current.get_mut().unwrap();
+        current.start = now;
+        current.scopes.clear();
+    }
+
+    pub fn end_frame(&mut self) {
+        let current = self.current.get_mut().unwrap();
+        current.end = self.created.elapsed();
+        std::mem::swap(current, &mut self.frames[self.next_frame]);
+        self.next_frame = (self.next_frame + 1) % HISTORY;
+    }
+
+    /// Times a scope on the main thread, until the returned guard is dropped.
+    pub fn scope(&self, name: &'static str) -> Scope<'_> {
+        self.scope_on_thread(name, 0)
+    }
+
+    /// Times a scope on a thread of the thread pool, until the returned guard is dropped.
+    pub fn scope_on_thread(&self, name: &'static str, thread_index: usize) -> Scope<'_> {
+        Scope {
+            profiler: self,
+            name,
+            thread_index,
+            start: self.created.elapsed(),
+        }
+    }
+
+    /// All the finished frames that are remembered, from the oldest to the newest.
+    /// Frames that haven't happened yet are empty.
+    pub fn frames(&self) -> impl Iterator<Item = &FrameTimings> {
+        self.frames[self.next_frame..]
+            .iter()
+            .chain(self.frames[..self.next_frame].iter())
+    }
+
+    /// Draws a bar for every remembered frame into the bottom left corner of the buffer.
+    /// Every scope on the main thread gets its own color, and the line marks 1/40 seconds.
+    pub fn draw_graph(&self, buffer: &mut [u32], width: usize, height: usize) {
+        const GRAPH_HEIGHT: usize = 100;
+        const PIXELS_PER_MS: f32 = 2.0;
+
+        if width < HISTORY || height < GRAPH_HEIGHT {
+            return;
+        }
+
+        for (x, frame) in self.frames().enumerate() {
+            for y in 0..GRAPH_HEIGHT {
+                let pixel = &mut buffer[(height - 1 - y) * width + x];
+                *pixel = (*pixel >> 2) & 0x3f3f3f;
+            }
+
+            for scope in frame.scopes.iter().filter(|scope| scope.thread_index == 0) {
+                let to_y = |time: Duration| {
+                    let ms = time
+                        .checked_sub(frame.start)
+                        .unwrap_or_default()
+                        .as_secs_f32()
+                        * 1000.0;
+                    ((ms * PIXELS_PER_MS) as usize).min(GRAPH_HEIGHT)
+                };
+
+                let color = scope_color(scope.name);
+                for y in to_y(scope.start)..to_y(scope.end) {
+                    buffer[(height - 1 - y) * width + x] = color;
+                }
+            }
+        }
+
+        let target_y = (1000.0 / 40.0 * PIXELS_PER_MS) as usize;
+        for x in 0..HISTORY {
+            buffer[(height - 1 - target_y) * width + x] = 0xffffff;
+        }
+    }
+
+    /// Writes all the remembered frames as a trace that can be opened in
+    /// the chrome tracing tool (chrome://tracing).
+    pub fn write_chrome_trace(&self, path: &str) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "{{\"traceEvents\":[")?;
+        let mut first = true;
+        for frame in self.frames().filter(|frame| frame.end > frame.start) {
+            let frame_event = ScopeTiming {
+                name: "frame",
+                thread_index: 0,
+                start: frame.start,
+                end: frame.end,
+            };
+            for scope in std::iter::once(&frame_event).chain(frame.scopes.iter()) {
+                if !first {
+                    writeln!(file, ",")?;
+                }
+                first = false;
+                write!(
+                    file,
+                    "{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{},\"dur\":{}}}",
+                    scope.name,
+                    scope.thread_index,
+                    scope.start.as_micros(),
+                    (scope.end - scope.start).as_micros(),
+                )?;
+            }
+        }
+        writeln!(file, "\n]}}")?;
+        Ok(())
+    }
+}
+
+/// Records the time of a scope when it's dropped.
+pub struct Scope<'a> {
+    profiler: &'a Profiler,
+    name: &'static str,
+    thread_index: usize,
+    start: Duration,
+}
+
+impl Drop for Scope<'_> {
+    fn drop(&mut self) {
+        let end = self.profiler.created.elapsed();
+        self.profiler
+            .current
+            .lock()
+            .unwrap()
+            .scopes
+            .push(ScopeTiming {
+                name: self.name,
+                thread_index: self.thread_index,
+                start: self.start,
+                end,
+            });
+    }
+}
+
+/// Picks a bright color for a scope, that stays the same between frames.
+fn scope_color(name: &str) -> u32 {
+    let hash = name.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    (hash | 0x404040) & 0xffffff
+}
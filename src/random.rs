@@ -3,14 +3,17 @@ pub struct Random(u32);
 
 impl Random {
     pub fn new() -> Self {
+        Self::with_seed(Self::time_seed())
+    }
+
+    /// A seed that is different every time the game is started.
+    pub fn time_seed() -> u32 {
         use std::time::{SystemTime, UNIX_EPOCH};
-        Self::with_seed(
-            (SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Cannot generate starting seed with epoch")
-                .as_nanos()
-                % 0xffff_ffff) as u32,
-        )
+        (SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Cannot generate starting seed with epoch")
+            .as_nanos()
+            % 0xffff_ffff) as u32
     }
 
     pub fn with_seed(seed: u32) -> Self {
@@ -1,25 +1,75 @@
+mod scene;
+
 use crate::float_range;
-use crate::texture::VerticalImage;
-use std::marker::PhantomData;
+use crate::texture::{BlendMode, VerticalImage};
+use crate::{Mat2, Vec2};
+pub use scene::Renderer;
+
+/// Where the player is, and which way they're looking.
+#[derive(Clone, Copy)]
+pub struct Camera {
+    pub pos: Vec2,
+    pub matrix: Mat2,
+    /// The aspect ratio of the screen, which decides how wide the view is.
+    pub aspect: f32,
+}
+
+/// What a frame shows, and the buffer it's rendered into.
+pub struct Frame<'b> {
+    pub camera: Camera,
+    /// The pixels of the screen, row by row.
+    pub buffer: &'b mut [u32],
+    pub width: usize,
+    pub height: usize,
+    /// The time that animations are at.
+    pub world_time: f32,
+    pub settings: RenderSettings,
+}
+
+/// How textures are sampled when they are drawn.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureFilter {
+    /// Picks the closest pixel, which gives the blocky look.
+    Nearest,
+    /// Blends between the four closest pixels.
+    Bilinear,
+}
 
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub filter: TextureFilter,
+    /// If true, a smaller version of the image is used when the image is drawn small
+    /// on the screen, which stops distant walls from shimmering.
+    pub mip_maps: bool,
+    /// The smallest number of columns a thread renders at once. The screen is split up
+    /// into bigger pieces at first, and smaller ones towards the end of the frame.
+    pub min_split_size: usize,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            filter: TextureFilter::Nearest,
+            mip_maps: true,
+            min_split_size: 8,
+        }
+    }
+}
+
+/// A single column of pixels on the screen, from the top to the bottom.
 pub struct ImageColumn<'a> {
-    // The buffer, as well as buffer.add(stride), buffer.add(stride * 2) e.t.c. until
-    // buffer.add(stride * (height - 1)) should only be accessed by this struct for 'a,
-    // and should be valid for 'a.
-    buffer: *mut u32,
-    stride: usize,
-    height: usize,
-    _phantom: PhantomData<&'a mut [u32]>,
+    pixels: &'a mut [u32],
+    settings: RenderSettings,
 }
 
 impl<'a> ImageColumn<'a> {
-    pub unsafe fn from_raw(buffer: *mut u32, stride: usize, height: usize) -> Self {
-        Self {
-            buffer,
-            stride,
-            height,
-            _phantom: PhantomData,
-        }
+    pub fn new(pixels: &'a mut [u32], settings: RenderSettings) -> Self {
+        Self { pixels, settings }
+    }
+
+    #[inline]
+    fn height(&self) -> usize {
+        self.pixels.len()
     }
 
     /// Draws a cropped image.
@@ -30,7 +80,7 @@ impl<'a> ImageColumn<'a> {
     pub fn draw_partial_image(
         &mut self,
         image: &VerticalImage,
-        image_x: usize,
+        image_u: f32,
         mut crop_y_start: f32,
         mut crop_y_end: f32,
         pos_y_start: f32,
@@ -58,15 +108,58 @@ impl<'a> ImageColumn<'a> {
             pos_y_end
         };
 
+        let image = if self.settings.mip_maps {
+            // How many pixels of the image end up on a single pixel of the screen.
+            let pixels_per_pixel = ((crop_y_end - crop_y_start) * image.height() as f32)
+                / ((pos_y_end - pos_y_start) * self.height() as f32);
+            image.mip_level(pixels_per_pixel.log2().floor().max(0.0) as usize)
+        } else {
+            image
+        };
+
+        match self.settings.filter {
+            TextureFilter::Nearest => self.draw_nearest(
+                image,
+                image_u,
+                crop_y_start,
+                crop_y_end,
+                pos_y_start,
+                pos_y_end,
+                dimming,
+            ),
+            TextureFilter::Bilinear => self.draw_bilinear(
+                image,
+                image_u,
+                crop_y_start,
+                crop_y_end,
+                pos_y_start,
+                pos_y_end,
+                dimming,
+            ),
+        }
+    }
+
+    fn draw_nearest(
+        &mut self,
+        image: &VerticalImage,
+        image_u: f32,
+        crop_y_start: f32,
+        crop_y_end: f32,
+        pos_y_start: f32,
+        pos_y_end: f32,
+        dimming: f32,
+    ) {
+        let image_x = ((image_u * image.width() as f32) as usize).min(image.width() - 1);
         let mut pixel_iter = float_range::range(
             crop_y_start * image.height() as f32,
             crop_y_end * image.height() as f32,
         );
         let mut from_pixel = pixel_iter.next().unwrap();
-        let d_pixel = ((pos_y_end - pos_y_start) * self.height as f32)
+        let d_pixel = ((pos_y_end - pos_y_start) * self.height() as f32)
             / ((crop_y_end - crop_y_start) * image.height() as f32);
-        let mut self_y = pos_y_start * self.height as f32;
+        let mut self_y = pos_y_start * self.height() as f32;
         let strip = &image.pixels()[image.height() * image_x..];
+        let blend_mode = image.blend_mode();
         for to_pixel in pixel_iter {
             let pix = strip[(from_pixel as usize).min(image.height() - 1)];
             let self_y_end = self_y + d_pixel * (to_pixel - from_pixel);
@@ -74,11 +167,8 @@ impl<'a> ImageColumn<'a> {
             if let Some(pix) = pix {
                 let dimmed = dim_color(pix, dimming);
 
-                for buffer_index in self_y as usize..(self_y_end as usize).min(self.height - 1) {
-                    unsafe {
-                        *self.buffer.add(buffer_index * self.stride) =
-                            dimmed.0 as u32 * 256 * 256 + dimmed.1 as u32 * 256 + dimmed.2 as u32;
-                    }
+                for buffer_index in self_y as usize..(self_y_end as usize).min(self.height() - 1) {
+                    self.blend_pixel(buffer_index, dimmed, pix[3], blend_mode);
                 }
             }
 
@@ -86,14 +176,113 @@ impl<'a> ImageColumn<'a> {
             from_pixel = to_pixel;
         }
     }
+
+    fn draw_bilinear(
+        &mut self,
+        image: &VerticalImage,
+        image_u: f32,
+        crop_y_start: f32,
+        crop_y_end: f32,
+        pos_y_start: f32,
+        pos_y_end: f32,
+        dimming: f32,
+    ) {
+        let width = image.width();
+        let height = image.height();
+        let pixels = image.pixels();
+        let blend_mode = image.blend_mode();
+
+        let image_x = (image_u * width as f32 - 0.5).max(0.0);
+        let x0 = (image_x as usize).min(width - 1);
+        let x1 = (x0 + 1).min(width - 1);
+        let fract_x = image_x.fract();
+
+        let self_y_start = pos_y_start * self.height() as f32;
+        let d_image_y = ((crop_y_end - crop_y_start) * height as f32)
+            / ((pos_y_end - pos_y_start) * self.height() as f32);
+
+        for buffer_index in self_y_start as usize
+            ..((pos_y_end * self.height() as f32) as usize).min(self.height() - 1)
+        {
+            let image_y = (crop_y_start * height as f32
+                + (buffer_index as f32 + 0.5 - self_y_start) * d_image_y
+                - 0.5)
+                .max(0.0);
+            let y0 = (image_y as usize).min(height - 1);
+            let y1 = (y0 + 1).min(height - 1);
+            let fract_y = image_y.fract();
+
+            // Colors are premultiplied by alpha while blending, so that
+            // transparent pixels don't bleed their color into the opaque ones.
+            let mut sum = [0.0; 4];
+            for &(x, y, weight) in &[
+                (x0, y0, (1.0 - fract_x) * (1.0 - fract_y)),
+                (x1, y0, fract_x * (1.0 - fract_y)),
+                (x0, y1, (1.0 - fract_x) * fract_y),
+                (x1, y1, fract_x * fract_y),
+            ] {
+                if let Some(pixel) = pixels[x * height + y] {
+                    let weight = weight * pixel[3];
+                    sum[0] += pixel[0] * weight;
+                    sum[1] += pixel[1] * weight;
+                    sum[2] += pixel[2] * weight;
+                    sum[3] += weight;
+                }
+            }
+
+            if sum[3] > 0.0 {
+                let pix = [sum[0] / sum[3], sum[1] / sum[3], sum[2] / sum[3], sum[3]];
+                self.blend_pixel(buffer_index, dim_color(pix, dimming), pix[3], blend_mode);
+            }
+        }
+    }
+
+    #[inline]
+    fn blend_pixel(&mut self, y: usize, color: [f32; 3], alpha: f32, blend_mode: BlendMode) {
+        let dst = &mut self.pixels[y];
+        *dst = match blend_mode {
+            BlendMode::Alpha if alpha >= 1.0 => pack_color(color),
+            BlendMode::Alpha => {
+                let under = unpack_color(*dst);
+                pack_color([
+                    under[0] + (color[0] - under[0]) * alpha,
+                    under[1] + (color[1] - under[1]) * alpha,
+                    under[2] + (color[2] - under[2]) * alpha,
+                ])
+            }
+            BlendMode::Additive => {
+                let under = unpack_color(*dst);
+                pack_color([
+                    under[0] + color[0] * alpha,
+                    under[1] + color[1] * alpha,
+                    under[2] + color[2] * alpha,
+                ])
+            }
+        };
+    }
+}
+
+#[inline]
+fn dim_color(color: [f32; 4], dim_factor: f32) -> [f32; 3] {
+    [
+        color[0] * dim_factor * dim_factor,
+        color[1] * dim_factor * dim_factor,
+        color[2] * dim_factor,
+    ]
+}
+
+#[inline]
+fn pack_color(color: [f32; 3]) -> u32 {
+    (color[0].min(255.0) as u32) << 16
+        | (color[1].min(255.0) as u32) << 8
+        | color[2].min(255.0) as u32
 }
 
 #[inline]
-fn dim_color(color: [f32; 3], dim_factor: f32) -> (u8, u8, u8, u8) {
-    (
-        (color[0] * dim_factor * dim_factor) as u8,
-        (color[1] * dim_factor * dim_factor) as u8,
-        (color[2] * dim_factor) as u8,
-        255,
-    )
+fn unpack_color(color: u32) -> [f32; 3] {
+    [
+        ((color >> 16) & 0xff) as f32,
+        ((color >> 8) & 0xff) as f32,
+        (color & 0xff) as f32,
+    ]
 }
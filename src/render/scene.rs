@@ -0,0 +1,680 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{Frame, ImageColumn, RenderSettings};
+use crate::alloc::{AllocStats, ALLOCATOR};
+use crate::bitset::BitSet;
+use crate::profiler::Profiler;
+use crate::raycast::{raycast, Raycast};
+use crate::texture::{Animation, Textures, VerticalImage};
+use crate::threading::{GuidedChunksMut, ThreadPool};
+use crate::world::{ExploredTiles, SpriteId, StaticSprite, World};
+use crate::{Mat2, Vec2};
+
+/// Everything needed to render a frame, shared by all the threads.
+struct View<'v, 'a> {
+    world: &'v World,
+    textures: &'a Textures,
+    sprites: &'v [ProjectedSprite],
+    /// The width of the entire screen.
+    stride: usize,
+    height: usize,
+    cam_matrix: Mat2,
+    inv_cam_matrix: Mat2,
+    cam_pos: Vec2,
+    aspect: f32,
+    world_time: f32,
+    settings: RenderSettings,
+}
+
+pub struct Renderer<'a> {
+    /// One per thread in the thread pool.
+    scratch: Vec<Mutex<RenderScratch<'a>>>,
+    sprite_ids: Vec<SpriteId>,
+    visible_sprites: Vec<ProjectedSprite>,
+    /// The screen, stored column by column, so that every thread can get
+    /// its own strip of columns to render into. Drawing a column then walks
+    /// through memory in order, which makes rendering into this and copying
+    /// it over to the rows afterwards faster than drawing straight into the rows.
+    columns: Vec<u32>,
+    stats: RenderStats,
+}
+
+/// How long it took to render a strip of columns.
+#[derive(Clone, Copy, Debug)]
+pub struct JobTiming {
+    pub x_start: usize,
+    pub width: usize,
+    pub thread_index: usize,
+    pub time: Duration,
+    pub allocations: AllocStats,
+}
+
+/// Statistics about the last frame that was rendered.
+#[derive(Default)]
+pub struct RenderStats {
+    pub min_split_size: usize,
+    pub n_threads: usize,
+    pub jobs: Vec<JobTiming>,
+}
+
+impl RenderStats {
+    /// The total time every thread spent rendering.
+    pub fn thread_times(&self) -> impl Iterator<Item = Duration> + '_ {
+        (0..self.n_threads).map(move |thread_index| {
+            self.jobs
+                .iter()
+                .filter(|job| job.thread_index == thread_index)
+                .map(|job| job.time)
+                .sum()
+        })
+    }
+
+    /// The allocations every thread made while rendering.
+    pub fn thread_allocations(&self) -> impl Iterator<Item = AllocStats> + '_ {
+        (0..self.n_threads).map(move |thread_index| {
+            let mut total = AllocStats::default();
+            for job in self
+                .jobs
+                .iter()
+                .filter(|job| job.thread_index == thread_index)
+            {
+                total += job.allocations;
+            }
+            total
+        })
+    }
+}
+
+impl std::fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let total: Duration = self.jobs.iter().map(|job| job.time).sum();
+        let min = self
+            .jobs
+            .iter()
+            .map(|job| job.time)
+            .min()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{} render jobs (min split size {}), job time min {:?} / avg {:?}",
+            self.jobs.len(),
+            self.min_split_size,
+            min,
+            total / self.jobs.len().max(1) as u32,
+        )?;
+        if let Some(slowest) = self.jobs.iter().max_by_key(|job| job.time) {
+            writeln!(
+                f,
+                "slowest job: {:?} for columns {}..{}",
+                slowest.time,
+                slowest.x_start,
+                slowest.x_start + slowest.width,
+            )?;
+        }
+        write!(f, "render time per thread:")?;
+        for time in self.thread_times() {
+            write!(f, " {:?}", time)?;
+        }
+        write!(f, "\nrender allocations per thread:")?;
+        for allocations in self.thread_allocations() {
+            write!(f, " {}", allocations.count)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new() -> Self {
+        Self {
+            scratch: Vec::new(),
+            sprite_ids: Vec::new(),
+            visible_sprites: Vec::new(),
+            columns: Vec::new(),
+            stats: RenderStats::default(),
+        }
+    }
+
+    /// Marks the tiles that were seen in the last frame as explored.
+    ///
+    /// Every thread keeps track of the tiles it has seen by itself while rendering,
+    /// so that they don't have to share anything, and the tiles are merged here.
+    pub fn mark_explored(&mut self, explored: &mut ExploredTiles) {
+        for scratch in self.scratch.iter_mut() {
+            let seen_tiles = &mut scratch.get_mut().unwrap().seen_tiles;
+            if seen_tiles.len() == explored.n_tiles() {
+                explored.merge(seen_tiles);
+            }
+            seen_tiles.clear();
+        }
+    }
+
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+
+    pub fn raycast_scene(
+        &mut self,
+        thread_pool: &mut ThreadPool,
+        world: &World,
+        textures: &'a Textures,
+        frame: Frame<'_>,
+        profiler: &Profiler,
+    ) {
+        let Frame {
+            camera,
+            buffer,
+            width,
+            height,
+            world_time,
+            settings,
+        } = frame;
+        assert_eq!(width * height, buffer.len());
+
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.project_sprites(world, camera.pos, camera.matrix, width, camera.aspect);
+
+        while self.scratch.len() < thread_pool.n_threads() {
+            self.scratch.push(Mutex::new(RenderScratch::default()));
+        }
+        // Any thread could end up doing every job, so each one needs room for all their timings.
+        let min_split_size = settings.min_split_size.max(1);
+        let max_jobs = (width + min_split_size - 1) / min_split_size;
+        for scratch in self.scratch.iter_mut() {
+            let scratch = scratch.get_mut().unwrap();
+            scratch.timings.reserve(max_jobs);
+            if scratch.seen_tiles.len() != world.explored.n_tiles() {
+                scratch.seen_tiles.reset(world.explored.n_tiles());
+            }
+        }
+        self.columns.resize(width * height, 0);
+
+        let view = View {
+            world,
+            textures,
+            sprites: &self.visible_sprites,
+            stride: width,
+            height,
+            cam_matrix: camera.matrix,
+            inv_cam_matrix: crate::inverse_mat2(camera.matrix),
+            cam_pos: camera.pos,
+            aspect: camera.aspect,
+            world_time,
+            settings,
+        };
+        let scratch = &self.scratch;
+        let n_threads = thread_pool.n_threads();
+        thread_pool.for_each(
+            GuidedChunksMut::new(
+                &mut self.columns,
+                height,
+                settings.min_split_size,
+                n_threads,
+            ),
+            |(x_offset, strip), thread_index| {
+                let _scope = profiler.scope_on_thread("raycast", thread_index);
+                let start = Instant::now();
+                let allocations_before = ALLOCATOR.thread_stats();
+                let mut scratch = scratch[thread_index].lock().unwrap();
+                render_strip(&view, strip, x_offset, &mut scratch);
+                scratch.timings.push(JobTiming {
+                    x_start: x_offset,
+                    width: strip.len() / height,
+                    thread_index,
+                    time: start.elapsed(),
+                    allocations: ALLOCATOR.thread_stats().since(allocations_before),
+                });
+            },
+        );
+
+        // Which jobs a thread ends up with changes from frame to frame, so every thread gets
+        // as much room as the one that needed the most, to not have to grow later on.
+        let max_hits = self
+            .scratch
+            .iter_mut()
+            .map(|scratch| scratch.get_mut().unwrap().hits.capacity())
+            .max()
+            .unwrap_or(0);
+        let max_columns = self
+            .scratch
+            .iter_mut()
+            .map(|scratch| scratch.get_mut().unwrap().columns.capacity())
+            .max()
+            .unwrap_or(0);
+        let max_static_tiles = self
+            .scratch
+            .iter_mut()
+            .map(|scratch| scratch.get_mut().unwrap().static_tiles.capacity())
+            .max()
+            .unwrap_or(0);
+        let max_static_sprites = self
+            .scratch
+            .iter_mut()
+            .map(|scratch| scratch.get_mut().unwrap().static_sprites.capacity())
+            .max()
+            .unwrap_or(0);
+
+        self.stats.min_split_size = settings.min_split_size;
+        self.stats.n_threads = n_threads;
+        self.stats.jobs.clear();
+        for scratch in self.scratch.iter_mut() {
+            let scratch = scratch.get_mut().unwrap();
+            scratch.hits.reserve(max_hits - scratch.hits.len());
+            scratch.columns.reserve(max_columns - scratch.columns.len());
+            scratch
+                .static_tiles
+                .reserve(max_static_tiles - scratch.static_tiles.len());
+            scratch
+                .static_sprites
+                .reserve(max_static_sprites - scratch.static_sprites.len());
+            self.stats.jobs.extend(scratch.timings.drain(..));
+        }
+
+        // Copy the columns over to the rows of the screen.
+        let columns = &self.columns;
+        thread_pool.for_each(
+            buffer.chunks_mut(width * 8).enumerate(),
+            |(i, rows), thread_index| {
+                let _scope = profiler.scope_on_thread("copy columns", thread_index);
+                for (y, row) in rows.chunks_mut(width).enumerate() {
+                    let y = i * 8 + y;
+                    for (x, pixel) in row.iter_mut().enumerate() {
+                        *pixel = columns[x * height + y];
+                    }
+                }
+            },
+        );
+    }
+
+    /// Collects all the sprites that could be visible this frame, figures out which
+    /// columns of the screen they cover, and sorts them from far away to close by.
+    ///
+    /// Static sprites are left out, they're projected by the strips whose rays reach them.
+    fn project_sprites(
+        &mut self,
+        world: &World,
+        cam_pos: Vec2,
+        cam_matrix: Mat2,
+        width: usize,
+        aspect: f32,
+    ) {
+        let max_distance = Raycast::default().max_distance;
+        let inv_cam_matrix = crate::inverse_mat2(cam_matrix);
+
+        self.sprite_ids.clear();
+        self.visible_sprites.clear();
+        let range = max_distance.ceil() as isize;
+        let (cam_x, cam_y) = (cam_pos.x.floor() as isize, cam_pos.y.floor() as isize);
+        for y in cam_y - range..=cam_y + range {
+            for x in cam_x - range..=cam_x + range {
+                if let Some(tile) = world.tiles.get(x, y) {
+                    self.sprite_ids.extend_from_slice(&tile.sprites_inside);
+                }
+            }
+        }
+
+        // Sprites can be in several tiles at once, but should only be drawn once.
+        self.sprite_ids.sort_unstable_by_key(|id| id.into_index());
+        self.sprite_ids.dedup();
+
+        for &sprite_id in self.sprite_ids.iter() {
+            let sprite = world.get_sprite(sprite_id).unwrap();
+            let projected = project_sprite(
+                sprite.pos(),
+                sprite.size(),
+                cam_pos,
+                inv_cam_matrix,
+                width,
+                aspect,
+            );
+            let (rel_pos, x_start, x_end) = match projected {
+                Some(projected) => projected,
+                None => continue,
+            };
+
+            // The angle between where the sprite is facing and the camera.
+            let to_cam = Mat2::identity().rotated_z(-sprite.rot) * (cam_pos - sprite.pos());
+            let view_angle = (-to_cam.x).atan2(to_cam.y);
+
+            self.visible_sprites.push(ProjectedSprite {
+                rel_pos,
+                view_angle,
+                size: sprite.size(),
+                y_pos: sprite.y_pos,
+                animation: sprite.animation,
+                x_start,
+                x_end,
+            });
+        }
+
+        self.visible_sprites
+            .sort_unstable_by(|a, b| b.rel_pos.y.partial_cmp(&a.rel_pos.y).unwrap());
+    }
+}
+
+/// Figures out where a sprite is relative to the camera, and which columns of the screen
+/// it covers, if it's in front of the camera and on the screen.
+fn project_sprite(
+    pos: Vec2,
+    size: f32,
+    cam_pos: Vec2,
+    inv_cam_matrix: Mat2,
+    width: usize,
+    aspect: f32,
+) -> Option<(Vec2, usize, usize)> {
+    let max_distance = Raycast::default().max_distance;
+    let rel_pos = inv_cam_matrix * (pos - cam_pos);
+    if rel_pos.y <= 0.0 || rel_pos.y >= max_distance {
+        return None;
+    }
+
+    // The inverse of the calculation of `fx` in `render_strip`, for
+    // the left and the right edge of the sprite.
+    let left_fx = (rel_pos.x + size * 0.5) / rel_pos.y;
+    let right_fx = (rel_pos.x - size * 0.5) / rel_pos.y;
+    let x_start = (width as f32 * (0.5 - left_fx * aspect)).floor().max(0.0);
+    let x_end = (width as f32 * (0.5 - right_fx * aspect))
+        .ceil()
+        .min(width as f32);
+    if x_start >= x_end {
+        return None;
+    }
+    Some((rel_pos, x_start as usize, x_end as usize))
+}
+
+/// A sprite that was projected onto the screen, once for the entire frame,
+/// or for a strip in the case of static sprites.
+struct ProjectedSprite {
+    /// The position of the sprite relative to the camera, where y is the depth.
+    rel_pos: Vec2,
+    view_angle: f32,
+    size: f32,
+    y_pos: f32,
+    animation: Animation,
+    /// The range of screen columns the sprite may cover.
+    x_start: usize,
+    x_end: usize,
+}
+
+#[derive(Clone, Copy)]
+struct HitData<'a> {
+    dist: f32,
+    size: f32,
+    uv: f32,
+    image: &'a VerticalImage,
+    y_pos: f32,
+}
+
+impl HitData<'_> {
+    fn draw(&self, column: &mut ImageColumn) {
+        let dist_size = 1.0f32 / (0.0000001 + self.dist);
+        column.draw_partial_image(
+            self.image,
+            self.uv,
+            0.0,
+            1.0,
+            0.5 - dist_size * 0.5 + dist_size * (self.y_pos * (1.0 - self.size)),
+            0.5 - dist_size * 0.5 + dist_size * (self.y_pos * (1.0 - self.size) + self.size),
+            1.0 / (1.0 + self.dist * self.dist * 0.2),
+        );
+    }
+}
+
+/// The depth buffer for a single column.
+struct ColumnData {
+    /// The distance to the closest solid wall.
+    depth: f32,
+    /// The transparent walls in front of the solid wall, sorted from close by to far
+    /// away, are `hits[hits_start..hits_end]`. Once they are drawn `hits_end` is moved
+    /// back, so they are not drawn again.
+    hits_start: usize,
+    hits_end: usize,
+}
+
+/// Buffers that are reused between strips, to avoid allocations.
+#[derive(Default)]
+struct RenderScratch<'a> {
+    hits: Vec<HitData<'a>>,
+    columns: Vec<ColumnData>,
+    timings: Vec<JobTiming>,
+    /// The tiles the rays have passed through or hit, indexed like `ExploredTiles`.
+    seen_tiles: BitSet,
+    /// The tiles with static sprites in them that the rays of the strip have reached, and
+    /// then the home tiles of those sprites.
+    static_tiles: Vec<(isize, isize)>,
+    /// The static sprites in `static_tiles`, sorted from far away to close by.
+    static_sprites: Vec<ProjectedSprite>,
+}
+
+fn render_strip<'a>(
+    view: &View<'_, 'a>,
+    strip: &mut [u32],
+    x_offset: usize,
+    scratch: &mut RenderScratch<'a>,
+) {
+    let View {
+        world,
+        textures,
+        sprites,
+        stride,
+        height,
+        cam_matrix,
+        inv_cam_matrix,
+        cam_pos,
+        aspect,
+        world_time,
+        settings,
+    } = *view;
+
+    let width = strip.len() / height;
+    for pixel in strip.iter_mut() {
+        *pixel = 0;
+    }
+
+    let RenderScratch {
+        hits,
+        columns,
+        seen_tiles,
+        static_tiles,
+        static_sprites,
+        ..
+    } = scratch;
+    hits.clear();
+    columns.clear();
+    static_tiles.clear();
+
+    let max_distance = Raycast::default().max_distance;
+
+    // The rays only visit the tiles after the one they start in.
+    let (cam_x, cam_y) = (cam_pos.x.floor() as isize, cam_pos.y.floor() as isize);
+    if let Some(index) = world.explored.index(cam_x, cam_y) {
+        seen_tiles.insert(index);
+    }
+    static_tiles.push((cam_x, cam_y));
+
+    // Draw all the solid walls, and remember the depth of the walls as well as
+    // the transparent walls in front of them.
+    for (x, column) in strip.chunks_mut(height).enumerate() {
+        let fx = (0.5 - (x + x_offset) as f32 / stride as f32) / aspect;
+        let offset = cam_matrix * Vec2::new(fx, 1.0);
+
+        let hits_start = hits.len();
+        let mut depth = max_distance;
+        let mut solid_hit = None;
+
+        raycast(
+            Raycast {
+                x: cam_pos.x,
+                y: cam_pos.y,
+                dx: offset.x,
+                dy: offset.y,
+                max_distance,
+            },
+            |dist, x, y, off_x, off_y, _pos| {
+                let tile = match world.tiles.get(x, y) {
+                    Some(tile) => tile,
+                    None => return false,
+                };
+                if let Some(index) = world.explored.index(x, y) {
+                    seen_tiles.insert(index);
+                }
+                if !tile.static_sprites.is_empty() {
+                    static_tiles.push((x, y));
+                }
+
+                match tile.get_graphics() {
+                    Some(graphics) => {
+                        let hit = HitData {
+                            dist,
+                            uv: off_x + off_y,
+                            image: textures.get_anim(&graphics.texture, world_time),
+                            size: 1.0,
+                            y_pos: 0.5,
+                        };
+                        if graphics.is_transparent {
+                            hits.push(hit);
+                        } else {
+                            depth = dist;
+                            solid_hit = Some(hit);
+                        }
+                        graphics.is_transparent
+                    }
+                    None => true,
+                }
+            },
+        );
+
+        if let Some(hit) = solid_hit {
+            hit.draw(&mut ImageColumn::new(column, settings));
+        }
+
+        columns.push(ColumnData {
+            depth,
+            hits_start,
+            hits_end: hits.len(),
+        });
+    }
+
+    // Static sprites are in every tile they stick into, and any part of them that can be seen
+    // is in a tile that a ray reached, so only the ones in those tiles have to be projected.
+    // They are projected from their home tiles, so that each of them is only projected once.
+    static_tiles.sort_unstable();
+    static_tiles.dedup();
+    let n_reached = static_tiles.len();
+    for i in 0..n_reached {
+        let (x, y) = static_tiles[i];
+        if let Some(tile) = world.tiles.get(x, y) {
+            static_tiles.extend(tile.static_sprites.iter().map(StaticSprite::home_tile));
+        }
+    }
+    static_tiles.drain(..n_reached);
+    static_tiles.sort_unstable();
+    static_tiles.dedup();
+
+    static_sprites.clear();
+    for &(x, y) in static_tiles.iter() {
+        let tile = match world.tiles.get(x, y) {
+            Some(tile) => tile,
+            None => continue,
+        };
+        for sprite in tile.static_sprites.iter() {
+            if sprite.home_tile() != (x, y) {
+                continue;
+            }
+            let projected = project_sprite(
+                sprite.pos,
+                sprite.size,
+                cam_pos,
+                inv_cam_matrix,
+                stride,
+                aspect,
+            );
+            if let Some((rel_pos, x_start, x_end)) = projected {
+                if x_start < x_offset + width && x_end > x_offset {
+                    static_sprites.push(ProjectedSprite {
+                        rel_pos,
+                        view_angle: 0.0,
+                        size: sprite.size,
+                        y_pos: sprite.y_pos,
+                        animation: sprite.animation,
+                        x_start,
+                        x_end,
+                    });
+                }
+            }
+        }
+    }
+    static_sprites.sort_unstable_by(|a, b| b.rel_pos.y.partial_cmp(&a.rel_pos.y).unwrap());
+
+    // Draw the sprites from far away to close by, testing them against the depth buffer.
+    // Transparent walls behind a sprite have to be drawn before it.
+    let mut sprites = sprites.iter().peekable();
+    let mut static_sprites = static_sprites.iter().peekable();
+    loop {
+        let sprite = match (sprites.peek(), static_sprites.peek()) {
+            (Some(sprite), Some(static_sprite)) if sprite.rel_pos.y >= static_sprite.rel_pos.y => {
+                sprites.next().unwrap()
+            }
+            (_, Some(_)) => static_sprites.next().unwrap(),
+            (Some(_), None) => sprites.next().unwrap(),
+            (None, None) => break,
+        };
+
+        let (x_start, x_end) = (
+            sprite.x_start.max(x_offset),
+            sprite.x_end.min(x_offset + width),
+        );
+        if x_start >= x_end {
+            continue;
+        }
+
+        let image = textures.get_anim_dir(&sprite.animation, world_time, sprite.view_angle);
+        for x in x_start..x_end {
+            let column_data = &mut columns[x - x_offset];
+            if sprite.rel_pos.y >= column_data.depth {
+                continue;
+            }
+
+            let fx = (0.5 - x as f32 / stride as f32) / aspect;
+            let hit_x = 0.5 + (sprite.rel_pos.x - fx * sprite.rel_pos.y) / sprite.size;
+            if hit_x < 0.0 || hit_x >= 1.0 {
+                continue;
+            }
+
+            let local_x = x - x_offset;
+            let mut column = ImageColumn::new(
+                &mut strip[local_x * height..(local_x + 1) * height],
+                settings,
+            );
+            while column_data.hits_end > column_data.hits_start
+                && hits[column_data.hits_end - 1].dist > sprite.rel_pos.y
+            {
+                column_data.hits_end -= 1;
+                hits[column_data.hits_end].draw(&mut column);
+            }
+
+            HitData {
+                dist: sprite.rel_pos.y,
+                uv: hit_x,
+                image,
+                size: sprite.size,
+                y_pos: sprite.y_pos,
+            }
+            .draw(&mut column);
+        }
+    }
+
+    // Draw the transparent walls that weren't behind any sprites.
+    for (column, column_data) in strip.chunks_mut(height).zip(columns.iter()) {
+        let mut column = ImageColumn::new(column, settings);
+        for hit in hits[column_data.hits_start..column_data.hits_end]
+            .iter()
+            .rev()
+        {
+            hit.draw(&mut column);
+        }
+    }
+}
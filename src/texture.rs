@@ -1,60 +1,171 @@
 use std::path::Path;
 
 macro_rules! create_textures {
-	($($name:ident = $file_name:tt $total_anim_time:tt),*,) => {
+	($($name:ident = $file_name:tt $total_anim_time:tt $blend_mode:ident $n_directions:tt),*,) => {
 		#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 		#[repr(u16)]
 		pub enum Texture {
 			$($name),*
 		}
 
-		const TEXTURE_DATA: &[(f32, &str)] = &[
-			$(($total_anim_time, $file_name)),*
+		const TEXTURE_DATA: &[(f32, &str, BlendMode, usize)] = &[
+			$(($total_anim_time, $file_name, BlendMode::$blend_mode, $n_directions)),*
 		];
+
+		impl Texture {
+			/// Finds a texture by its name, ignoring case, for textures named in asset files.
+			pub fn from_name(name: &str) -> Option<Self> {
+				$(if name.eq_ignore_ascii_case(stringify!($name)) {
+					return Some(Texture::$name);
+				})*
+				None
+			}
+		}
 	}
 }
 
+// Name = path, total animation time, blend mode, number of directions.
+//
+// A texture with more than one direction is a sprite sheet, where every frame
+// contains all the directions side by side, each one equally wide.
+// The first direction is the sprite seen from the front, and the following ones
+// are seen from further and further around, in the same direction as `Transform::rot`.
 create_textures!(
-    Wall = "assets\\wall" 1.0,
-    Window = "assets\\window" 1.0,
-    Evil = "assets\\evil" 1.0,
-    Rick = "assets\\rick" 1.0,
-    Floor = "assets\\floor" 1.0,
-    Fungus = "assets\\fungus" 1.0,
-    Door = "assets\\door" 1.0,
-    DoorClose = "assets\\door_close" 1.0,
+    Wall = "assets\\wall" 1.0 Alpha 1,
+    Window = "assets\\window" 1.0 Alpha 1,
+    Evil = "assets\\evil_directions" 1.0 Alpha 4,
+    EvilWalk = "assets\\evil_walk" 0.6 Alpha 4,
+    EvilAttack = "assets\\evil_attack" 0.8 Alpha 4,
+    Rick = "assets\\rick" 1.0 Alpha 1,
+    RickWalk = "assets\\rick_walk" 0.5 Alpha 1,
+    Floor = "assets\\floor" 1.0 Alpha 1,
+    Fungus = "assets\\fungus" 1.0 Additive 1,
+    Door = "assets\\door" 1.0 Alpha 1,
+    DoorClose = "assets\\door_close" 1.0 Alpha 1,
 );
 
+/// How the pixels of a texture are combined with what is already in the buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlendMode {
+    /// Regular alpha blending, fully opaque pixels just overwrite the buffer.
+    Alpha,
+    /// The color is added on top of the buffer, scaled by alpha. Good for glowing things.
+    Additive,
+}
+
 struct TextureInfo {
     id: usize,
     n_animation_frames: usize,
+    /// The images of every frame are `n_directions` images after one another.
+    n_directions: usize,
     fps: f32,
 }
 
 pub struct VerticalImage {
     width: usize,
     height: usize,
-    pixels: Vec<Option<[f32; 3]>>,
+    blend_mode: BlendMode,
+    /// The rgb channels are between 0 and 255, the alpha channel is between 0 and 1.
+    /// Pixels with an alpha of zero are None, so they can be skipped quickly.
+    pixels: Vec<Option<[f32; 4]>>,
+    /// Successively halved versions of this image, built at load time.
+    /// The mip levels themselves never have mip levels of their own.
+    mips: Vec<VerticalImage>,
 }
 
 impl VerticalImage {
-    fn from_image(image: image::RgbaImage) -> Self {
+    fn from_image(image: image::RgbaImage, blend_mode: BlendMode) -> Self {
         use image::Pixel;
         let mut pixels = Vec::new();
         for x in 0..image.width() {
             for y in 0..image.height() {
                 let pixel = image.get_pixel(x, y).channels();
                 if pixel[3] != 0 {
-                    pixels.push(Some([pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]));
+                    pixels.push(Some([
+                        pixel[0] as f32,
+                        pixel[1] as f32,
+                        pixel[2] as f32,
+                        pixel[3] as f32 / 255.0,
+                    ]));
                 } else {
                     pixels.push(None);
                 }
             }
         }
-        VerticalImage {
+        let mut image = VerticalImage {
             pixels,
+            blend_mode,
             width: image.width() as usize,
             height: image.height() as usize,
+            mips: Vec::new(),
+        };
+
+        let mut mips = Vec::new();
+        let mut previous = &image;
+        while previous.width > 1 || previous.height > 1 {
+            mips.push(previous.downsampled());
+            previous = mips.last().unwrap();
+        }
+        image.mips = mips;
+
+        image
+    }
+
+    /// Creates an image that is half the size, where every pixel is the average
+    /// of the 2x2 pixels it covers. The colors are weighted by alpha, so that
+    /// transparent pixels don't bleed their color into the opaque ones.
+    fn downsampled(&self) -> Self {
+        let width = (self.width + 1) / 2;
+        let height = (self.height + 1) / 2;
+        let mut pixels = Vec::with_capacity(width * height);
+        for x in 0..width {
+            for y in 0..height {
+                let mut sum = [0.0; 4];
+                for &(from_x, from_y) in &[
+                    (x * 2, y * 2),
+                    (x * 2 + 1, y * 2),
+                    (x * 2, y * 2 + 1),
+                    (x * 2 + 1, y * 2 + 1),
+                ] {
+                    let from_x = from_x.min(self.width - 1);
+                    let from_y = from_y.min(self.height - 1);
+                    if let Some(pixel) = self.pixels[from_x * self.height + from_y] {
+                        sum[0] += pixel[0] * pixel[3];
+                        sum[1] += pixel[1] * pixel[3];
+                        sum[2] += pixel[2] * pixel[3];
+                        sum[3] += pixel[3];
+                    }
+                }
+
+                if sum[3] > 0.0 {
+                    pixels.push(Some([
+                        sum[0] / sum[3],
+                        sum[1] / sum[3],
+                        sum[2] / sum[3],
+                        sum[3] / 4.0,
+                    ]));
+                } else {
+                    pixels.push(None);
+                }
+            }
+        }
+
+        VerticalImage {
+            width,
+            height,
+            blend_mode: self.blend_mode,
+            pixels,
+            mips: Vec::new(),
+        }
+    }
+
+    /// Returns the given mip level, where 0 is the full size image.
+    /// Levels past the smallest one return the smallest one.
+    pub fn mip_level(&self, level: usize) -> &VerticalImage {
+        match level {
+            0 => self,
+            _ if self.mips.is_empty() => self,
+            _ => &self.mips[(level - 1).min(self.mips.len() - 1)],
         }
     }
 
@@ -66,7 +177,11 @@ impl VerticalImage {
         self.height
     }
 
-    pub fn pixels(&self) -> &[Option<[f32; 3]>] {
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    pub fn pixels(&self) -> &[Option<[f32; 4]>] {
         &self.pixels
     }
 }
@@ -81,7 +196,7 @@ impl Textures {
         let mut images = Vec::new();
         let textures = TEXTURE_DATA
             .iter()
-            .map(|&(total_time, path)| {
+            .map(|&(total_time, path, blend_mode, n_directions)| {
                 let path: &Path = path.as_ref();
                 match path.exists() {
                     true => {
@@ -94,7 +209,7 @@ impl Textures {
                             if !path.exists() {
                                 break;
                             }
-                            images.push(VerticalImage::from_image(image::open(&path)?.into_rgba()));
+                            push_frame(&mut images, &path, blend_mode, n_directions)?;
                             println!(" * {:?}", path);
                             n_animation_frames += 1;
                         }
@@ -108,6 +223,7 @@ impl Textures {
                         Ok(TextureInfo {
                             id,
                             n_animation_frames,
+                            n_directions,
                             fps: n_animation_frames as f32 / total_time,
                         })
                     }
@@ -115,11 +231,12 @@ impl Textures {
                         let mut path = path.to_path_buf();
                         path.set_extension("png");
                         let id = images.len();
-                        images.push(VerticalImage::from_image(image::open(&path)?.into_rgba()));
+                        push_frame(&mut images, &path, blend_mode, n_directions)?;
                         println!("loaded image {:?}", path);
                         Ok(TextureInfo {
                             id,
                             n_animation_frames: 1,
+                            n_directions,
                             fps: 1.0,
                         })
                     }
@@ -130,11 +247,21 @@ impl Textures {
         Ok(Self { textures, images })
     }
 
-    pub fn get(&self, texture: Texture) -> &VerticalImage {
-        &self.images[self.textures[texture as u16 as usize].id]
+    pub fn get_anim(&self, animation: &Animation, time: f32) -> &VerticalImage {
+        self.get_anim_dir(animation, time, 0.0)
     }
 
-    pub fn get_anim(&self, animation: &Animation, time: f32) -> &VerticalImage {
+    /// Returns the current frame of an animation, seen from the given angle.
+    ///
+    /// The view angle is the angle in radians between where the thing is facing
+    /// and the direction towards the viewer, so 0 means that it's seen from the front.
+    /// Textures with only one direction look the same from every angle.
+    pub fn get_anim_dir(
+        &self,
+        animation: &Animation,
+        time: f32,
+        view_angle: f32,
+    ) -> &VerticalImage {
         let texture = &self.textures[animation.texture as u16 as usize];
         let n_frames = (time - animation.start_time) * texture.fps * animation.speed;
 
@@ -143,8 +270,55 @@ impl Textures {
             AnimationKind::Clamped => (n_frames as usize).min(texture.n_animation_frames - 1),
         };
 
-        &self.images[texture.id + frame]
+        &self.images[texture.id
+            + frame * texture.n_directions
+            + direction_index(view_angle, texture.n_directions)]
+    }
+}
+
+fn direction_index(view_angle: f32, n_directions: usize) -> usize {
+    if n_directions <= 1 {
+        return 0;
+    }
+
+    let turns = view_angle / std::f32::consts::TAU;
+    let index = ((turns - turns.floor()) * n_directions as f32).round() as usize;
+    index % n_directions
+}
+
+/// Loads a single animation frame, and splits it up into its directions.
+fn push_frame(
+    images: &mut Vec<VerticalImage>,
+    path: &Path,
+    blend_mode: BlendMode,
+    n_directions: usize,
+) -> image::ImageResult<()> {
+    let image = image::open(path)?.into_rgba8();
+    if n_directions <= 1 {
+        images.push(VerticalImage::from_image(image, blend_mode));
+    } else {
+        if image.width() % n_directions as u32 != 0 {
+            return Err(image::ImageError::Parameter(
+                image::error::ParameterError::from_kind(image::error::ParameterErrorKind::Generic(
+                    format!(
+                        "{:?} is {} pixels wide, which can't be split into {} directions",
+                        path,
+                        image.width(),
+                        n_directions
+                    ),
+                )),
+            ));
+        }
+
+        let width = image.width() / n_directions as u32;
+        for direction in 0..n_directions as u32 {
+            let part =
+                image::imageops::crop_imm(&image, direction * width, 0, width, image.height());
+            images.push(VerticalImage::from_image(part.to_image(), blend_mode));
+        }
     }
+
+    Ok(())
 }
 
 #[derive(Clone, Copy)]
@@ -153,7 +327,7 @@ pub enum AnimationKind {
     Clamped,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy)]
 pub struct Animation {
     pub texture: Texture,
     pub start_time: f32,
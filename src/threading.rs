@@ -1,249 +1,250 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::thread::{sleep, spawn, JoinHandle};
-use std::time::Duration;
-
-use crate::raycast::{raycast, Raycast};
-use crate::render::ImageColumn;
-use crate::texture::{Textures, VerticalImage};
-use crate::world::World;
-use crate::{Mat2, Vec2};
-
-// TODO: It's weird to have rendering in the threading file, so,
-// either; make this more generic and move the rendering part somewhere else
-// or    ; include the thread pool in the renderer.
-
-const SPLIT_SIZE: usize = 64;
-
-/// A piece of work representing an area to raycast.
-struct RaycastWork {
-    world: *const World,
-    textures: *const Textures,
-    buffer: *mut u32,
-    stride: usize,
-    width: usize,
-    height: usize,
-    x_offset: usize,
-    cam_matrix: Mat2,
-    cam_pos: Vec2,
-    aspect: f32,
-    world_time: f32,
-}
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{spawn, JoinHandle};
+
+/// A function that all the threads in the pool should run. The lifetime is erased,
+/// `ThreadPool::broadcast` makes sure that it doesn't return before every thread
+/// is done with the function, so the function can never be used after it's gone.
+#[derive(Clone, Copy)]
+struct Task(*const (dyn Fn(usize) + Sync + 'static));
 
-// SAFETY: This is safe because we are not using thread local storage or anything.
-unsafe impl Send for RaycastWork {}
+// SAFETY: The function behind the pointer is Sync, so it can be called from any thread.
+unsafe impl Send for Task {}
+
+struct WorkQueue {
+    task: Option<Task>,
+    /// Increases every time there is a new task, so that the threads can tell
+    /// if the task is one they've already done.
+    generation: u64,
+    keep_running: bool,
+}
 
 struct SharedData {
-    work: Mutex<(u32, Vec<RaycastWork>)>,
-    keep_running: AtomicBool,
+    queue: Mutex<WorkQueue>,
+    /// Notified whenever there is a new task, or when the threads should stop.
+    work_available: Condvar,
+    /// Counts down as the threads finish the current task.
+    latch: Latch,
+    /// Set if the current task panicked on one of the threads.
+    panicked: AtomicBool,
+}
+
+/// A counter that can be waited on until it reaches zero.
+struct Latch {
+    remaining: Mutex<usize>,
+    done: Condvar,
 }
 
-pub struct ThreadPool<'a> {
+impl Latch {
+    fn new() -> Self {
+        Self {
+            remaining: Mutex::new(0),
+            done: Condvar::new(),
+        }
+    }
+
+    fn reset(&self, count: usize) {
+        *self.remaining.lock().unwrap() = count;
+    }
+
+    fn count_down(&self) {
+        let mut remaining = self.remaining.lock().unwrap();
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.done.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let mut remaining = self.remaining.lock().unwrap();
+        while *remaining > 0 {
+            remaining = self.done.wait(remaining).unwrap();
+        }
+    }
+}
+
+/// Waits for the latch when dropped, so that a task is waited for even if
+/// the calling thread panics while helping out with it.
+struct WaitOnDrop<'a>(&'a Latch);
+
+impl Drop for WaitOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.wait();
+    }
+}
+
+pub struct ThreadPool {
     threads: Vec<JoinHandle<()>>,
     shared: Arc<SharedData>,
-    hit_data_cache: Vec<HitData<'a>>,
 }
 
-impl ThreadPool<'_> {
+impl ThreadPool {
+    /// The number of threads to use if nothing else is specified, one per core.
+    pub fn default_thread_count() -> usize {
+        num_cpus::get()
+    }
+
+    /// Creates a thread pool where `n_threads` threads do the work.
+    /// The thread calling into the pool also helps out while it waits,
+    /// so only `n_threads - 1` threads are spawned.
     pub fn new(n_threads: usize) -> Self {
         let mut threads = Vec::new();
 
         let shared = Arc::new(SharedData {
-            work: Mutex::new((0, Vec::new())),
-            keep_running: AtomicBool::new(true),
+            queue: Mutex::new(WorkQueue {
+                task: None,
+                generation: 0,
+                keep_running: true,
+            }),
+            work_available: Condvar::new(),
+            latch: Latch::new(),
+            panicked: AtomicBool::new(false),
         });
 
-        for _ in 0..n_threads {
+        for thread_index in 1..n_threads.max(1) {
             let shared = shared.clone();
             threads.push(spawn(move || {
-                let mut hits = Vec::new();
-                while shared.keep_running.load(Ordering::SeqCst) {
-                    let mut lock = shared.work.lock().unwrap();
-                    if !lock.1.is_empty() {
-                        let work = lock.1.pop().unwrap();
-                        lock.0 += 1;
-                        std::mem::drop(lock);
-
-                        unsafe {
-                            run_work(work, &mut hits);
-                        }
-
-                        let mut lock = shared.work.lock().unwrap();
-                        lock.0 -= 1;
-                        std::mem::drop(lock);
-                    } else {
-                        std::mem::drop(lock);
-                        sleep(Duration::from_nanos(10));
+                let mut generation = 0;
+                loop {
+                    let mut queue = shared.queue.lock().unwrap();
+                    while queue.keep_running && queue.generation == generation {
+                        queue = shared.work_available.wait(queue).unwrap();
+                    }
+
+                    if !queue.keep_running {
+                        break;
+                    }
+
+                    generation = queue.generation;
+                    let task = queue.task.expect("A new generation needs a task");
+                    std::mem::drop(queue);
+
+                    // SAFETY: `broadcast` doesn't return until the latch is counted down,
+                    // so the task is still alive.
+                    let result =
+                        catch_unwind(AssertUnwindSafe(|| unsafe { (*task.0)(thread_index) }));
+                    if result.is_err() {
+                        shared.panicked.store(true, Ordering::SeqCst);
                     }
+
+                    shared.latch.count_down();
                 }
             }));
         }
 
-        Self {
-            threads,
-            shared,
-            hit_data_cache: Vec::new(),
-        }
+        Self { threads, shared }
     }
 
-    pub fn raycast_scene(
-        &mut self,
-        world: &World,
-        textures: &Textures,
-        cam_pos: Vec2,
-        cam_matrix: Mat2,
-        width: usize,
-        height: usize,
-        buffer: &mut [u32],
-        aspect: f32,
-        world_time: f32,
-    ) {
-        assert_eq!(width * height, buffer.len());
-
-        if height == 0 {
-            return;
-        }
+    /// The number of threads doing work, including the calling thread.
+    pub fn n_threads(&self) -> usize {
+        self.threads.len() + 1
+    }
+
+    /// Runs the function on every thread in the pool at once, including the calling thread,
+    /// and returns once all of them are done. The function is given the index of the thread
+    /// it's running on, which is below `n_threads`, and 0 for the calling thread.
+    ///
+    /// Since this doesn't return early, the function can borrow anything from the caller.
+    pub fn broadcast(&mut self, func: &(dyn Fn(usize) + Sync)) {
+        // SAFETY: This only erases the lifetime, see `Task`.
+        let task = Task(unsafe {
+            std::mem::transmute::<
+                *const (dyn Fn(usize) + Sync + '_),
+                *const (dyn Fn(usize) + Sync + 'static),
+            >(func)
+        });
 
-        for (i, chunk) in buffer[0..width].chunks_mut(SPLIT_SIZE).enumerate() {
-            self.shared.work.lock().unwrap().1.push(RaycastWork {
-                world,
-                textures,
-                buffer: chunk.as_mut_ptr(),
-                stride: width,
-                width: chunk.len(),
-                height,
-                cam_pos,
-                cam_matrix,
-                x_offset: i * SPLIT_SIZE,
-                aspect,
-                world_time,
-            });
+        self.shared.latch.reset(self.threads.len());
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            queue.task = Some(task);
+            queue.generation += 1;
         }
+        self.shared.work_available.notify_all();
 
-        while let Some(work) = {
-            let value = self.shared.work.lock().unwrap().1.pop();
-            value
-        } {
-            unsafe {
-                run_work(work, &mut self.hit_data_cache);
-            }
+        {
+            let _wait = WaitOnDrop(&self.shared.latch);
+            func(0);
         }
 
-        // While work is still being performed, wait
-        while self.shared.work.lock().unwrap().0 > 0 {
-            sleep(Duration::from_nanos(200));
+        self.shared.queue.lock().unwrap().task = None;
+
+        if self.shared.panicked.swap(false, Ordering::SeqCst) {
+            panic!("A task panicked on a thread in the thread pool");
         }
     }
 
+    /// Calls the function for every item of the iterator, spread out over all the threads.
+    /// The function is also given the index of the thread it's running on, so that
+    /// it can use per-thread buffers.
+    pub fn for_each<I>(&mut self, iter: I, func: impl Fn(I::Item, usize) + Sync)
+    where
+        I: Iterator + Send,
+    {
+        let iter = Mutex::new(iter);
+        self.broadcast(&|thread_index| loop {
+            let item = iter.lock().unwrap().next();
+            match item {
+                Some(item) => func(item, thread_index),
+                None => break,
+            }
+        });
+    }
+
     pub fn join(self) {
-        self.shared.keep_running.store(false, Ordering::SeqCst);
+        self.shared.queue.lock().unwrap().keep_running = false;
+        self.shared.work_available.notify_all();
         for thread in self.threads {
             let _ = thread.join();
         }
     }
 }
 
-#[derive(Clone, Copy)]
-struct HitData<'a> {
-    dist: f32,
-    size: f32,
-    uv: f32,
-    image: &'a VerticalImage,
-    y_pos: f32,
+/// Splits a slice into chunks that get smaller as less of the slice is left, so that
+/// the threads of a pool get big chunks at first, but still finish at around the same time.
+///
+/// The chunks are always a multiple of `unit` long, and yield the offset of the chunk in units.
+pub struct GuidedChunksMut<'a, T> {
+    slice: &'a mut [T],
+    offset: usize,
+    unit: usize,
+    min_units: usize,
+    n_threads: usize,
 }
 
-unsafe fn run_work(work: RaycastWork, hits: &mut Vec<HitData>) {
-    let RaycastWork {
-        world,
-        textures,
-        buffer,
-        stride,
-        width,
-        height,
-        x_offset,
-        cam_matrix,
-        cam_pos,
-        aspect,
-        world_time,
-    } = work;
-
-    // If the RaycastWork is valid, this should be valid too!
-    let world = &*world;
-    let textures = &*textures;
-
-    for x in 0..width {
-        let fx = (0.5 - (x + x_offset) as f32 / stride as f32) / aspect;
-        let offset = cam_matrix * Vec2::new(fx, 1.0);
-
-        let inv_cam_matrix = crate::inverse_mat2(cam_matrix);
-
-        hits.clear();
-
-        raycast(
-            Raycast {
-                x: cam_pos.x,
-                y: cam_pos.y,
-                dx: offset.x,
-                dy: offset.y,
-                ..Default::default()
-            },
-            |dist, x, y, off_x, off_y, _pos| {
-                let tile = world.tiles.get(x, y);
-                let should_continue = match tile {
-                    Some(tile) => match tile.get_graphics() {
-                        Some(graphics) => {
-                            hits.push(HitData {
-                                dist,
-                                uv: off_x + off_y,
-                                image: textures.get_anim(&graphics.texture, world_time),
-                                size: 1.0,
-                                y_pos: 0.5,
-                            });
-                            graphics.is_transparent
-                        }
-                        None => {
-                            for &sprite_id in tile.sprites_inside.iter() {
-                                let entity = world.get_sprite(sprite_id).unwrap();
-
-                                let rel_entity_pos = inv_cam_matrix * (entity.pos() - cam_pos);
-                                let hit_x = 0.5
-                                    + (rel_entity_pos.x - fx * rel_entity_pos.y) / entity.size();
-                                if hit_x >= 0.0 && hit_x < 1.0 {
-                                    hits.push(HitData {
-                                        dist: rel_entity_pos.y,
-                                        uv: hit_x,
-                                        image: textures.get(entity.texture),
-                                        size: entity.size(),
-                                        y_pos: entity.y_pos,
-                                    });
-                                }
-                            }
-
-                            true
-                        }
-                    },
-                    None => false,
-                };
-                should_continue
-            },
-        );
-
-        // Sort the graphics by distance
-        hits.sort_unstable_by(|a, b| a.dist.partial_cmp(&b.dist).unwrap());
-
-        let mut column = ImageColumn::from_raw(buffer.add(x), stride, height);
-
-        for hit in hits.iter().rev() {
-            let dist_size = 1.0f32 / (0.0000001 + hit.dist);
-            column.draw_partial_image(
-                hit.image,
-                ((hit.uv * hit.image.width() as f32) as usize).clamp(0, hit.image.width() - 1),
-                0.0,
-                1.0,
-                0.5 - dist_size * 0.5 + dist_size * (hit.y_pos * (1.0 - hit.size)),
-                0.5 - dist_size * 0.5 + dist_size * (hit.y_pos * (1.0 - hit.size) + hit.size),
-                1.0 / (1.0 + hit.dist * hit.dist * 0.2),
-            );
+impl<'a, T> GuidedChunksMut<'a, T> {
+    pub fn new(slice: &'a mut [T], unit: usize, min_units: usize, n_threads: usize) -> Self {
+        assert!(unit > 0);
+        Self {
+            slice,
+            offset: 0,
+            unit,
+            min_units: min_units.max(1),
+            n_threads: n_threads.max(1),
         }
     }
 }
+
+impl<'a, T> Iterator for GuidedChunksMut<'a, T> {
+    type Item = (usize, &'a mut [T]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = self.slice.len() / self.unit;
+        if remaining == 0 {
+            return None;
+        }
+
+        let units = ((remaining + self.n_threads * 2 - 1) / (self.n_threads * 2))
+            .max(self.min_units)
+            .min(remaining);
+
+        let slice = std::mem::take(&mut self.slice);
+        let (chunk, rest) = slice.split_at_mut(units * self.unit);
+        self.slice = rest;
+
+        let offset = self.offset;
+        self.offset += units;
+        Some((offset, chunk))
+    }
+}
@@ -1,55 +1,86 @@
+mod component;
 mod entity;
+mod explored;
 pub mod generate;
+mod systems;
 mod tiles;
 
+use crate::audio::Sound;
 use crate::id::{Id, IdMap};
 use crate::random::Random;
 use crate::texture::*;
 use crate::Vec2;
-pub use entity::{Entities, Entity, EntityId, Transform};
-pub use tiles::{Tile, TileKind, TileMap};
+pub use entity::{
+    AnimationState, Animator, Entities, Entity, EntityId, Footsteps, Health, Transform,
+};
+pub use explored::ExploredTiles;
+pub use tiles::{StaticSprite, Tile, TileKind, TileMap};
 
 create_id!(SpriteId);
 
+/// How close an evil enemy has to be to something to notice it.
+const ALERT_DISTANCE: f32 = 4.0;
+
+/// Updates one part of the world every step of the simulation.
+pub type System = fn(world: &mut World, time_step: f32, world_time: f32);
+
+/// A sound that something in the world made.
+#[derive(Clone, Copy)]
+pub struct SoundEvent {
+    pub sound: Sound,
+    pub pos: Vec2,
+}
+
 pub struct World {
     pub tiles: TileMap,
     pub entities: Entities,
+    /// The tiles the player has seen.
+    pub explored: ExploredTiles,
+    /// The sounds made while simulating, they stay here until someone takes them out.
+    pub sound_events: Vec<SoundEvent>,
     sprites: IdMap<SpriteId, Sprite>,
     random: Random,
+    systems: Vec<System>,
 }
 
 impl World {
+    /// Creates a world without any entities, that moves them around, plays their footsteps,
+    /// controls the evil enemies and animates them.
+    pub fn new(tiles: TileMap) -> Self {
+        let (width, height) = (tiles.width(), tiles.height());
+        let mut world = Self {
+            tiles,
+            entities: Entities::new(),
+            explored: ExploredTiles::new(width, height),
+            sound_events: Vec::new(),
+            sprites: IdMap::new(),
+            random: Random::new(),
+            systems: Vec::new(),
+        };
+        world.add_system(systems::move_entities);
+        world.add_system(systems::play_footsteps);
+        world.add_system(systems::update_evil_enemies);
+        world.add_system(systems::update_animators);
+        world
+    }
+
     pub fn insert_sprite(
         &mut self,
-        texture: Texture,
+        animation: Animation,
         pos: Vec2,
         size: f32,
         y_pos: f32,
     ) -> SpriteId {
         let sprite = Sprite {
-            texture,
+            animation,
             pos,
+            rot: 0.0,
             size,
             y_pos,
         };
 
-        let texture_x = sprite.pos.x;
-        let texture_y = sprite.pos.y;
-        let size = sprite.size;
-
         let id = self.sprites.insert(sprite);
-
-        for y in
-            (texture_y - size / 2.0).floor() as isize..=(texture_y + size / 2.0).floor() as isize
-        {
-            for x in (texture_x - size / 2.0).floor() as isize
-                ..=(texture_x + size / 2.0).floor() as isize
-            {
-                if let Some(tile) = self.tiles.get_mut(x, y) {
-                    tile.sprites_inside.push(id);
-                }
-            }
-        }
+        self.tiles.insert_sprite(id, pos, size);
 
         id
     }
@@ -58,77 +89,25 @@ impl World {
         self.sprites.get(id)
     }
 
-    pub fn simulate(&mut self, time_step: f32, _world_time: f32) {
-        for transform in self.entities.transforms.values_mut() {
-            transform.pos.x += transform.vel.x * time_step;
-            if self
-                .tiles
-                .square_is_colliding(transform.pos, transform.size)
-            {
-                transform.pos.x -= transform.vel.x * time_step;
-                transform.vel.x *= -1.0;
-            }
-
-            transform.pos.y += transform.vel.y * time_step;
-            if self
-                .tiles
-                .square_is_colliding(transform.pos, transform.size)
-            {
-                transform.pos.y -= transform.vel.y * time_step;
-                transform.vel.y *= -1.0;
-            }
-
-            transform.vel -= transform.vel * transform.drag * time_step;
-
-            if let Some(sprite_id) = transform.sprite {
-                self.tiles.move_sprite(
-                    sprite_id,
-                    self.sprites.get_mut(sprite_id).unwrap(),
-                    transform.pos,
-                );
-            }
-        }
+    /// Adds a system that runs every step of the simulation, after the ones added before it.
+    pub fn add_system(&mut self, system: System) {
+        self.systems.push(system);
+    }
 
-        for (entity_id, evil_enemy) in self.entities.evil_enemies.iter_mut() {
-            match evil_enemy {
-                entity::EvilEnemy::Wander(time) => {
-                    *time -= time_step;
-
-                    if *time < 0.0 {
-                        self.entities
-                            .transforms
-                            .get_mut(&entity_id)
-                            .expect("Evil enemy needs a transform")
-                            .vel +=
-                            Vec2::new(self.random.get_float() - 0.5, self.random.get_float() - 0.5);
-                        *time = self.random.get_float() * 3.0 + 1.0;
-                    }
-                }
-                entity::EvilEnemy::Angry(target) => match self.entities.transforms.get(target) {
-                    Some(target_transform) => {
-                        let target_pos = target_transform.pos;
-                        let evil_enemy_transform = self
-                            .entities
-                            .transforms
-                            .get_mut(&entity_id)
-                            .expect("Evil enemy needs a transform");
-
-                        evil_enemy_transform.vel +=
-                            (target_pos - evil_enemy_transform.pos) * time_step * 0.1;
-                    }
-                    None => {
-                        *evil_enemy = entity::EvilEnemy::Wander(2.0);
-                    }
-                },
-            }
+    pub fn simulate(&mut self, time_step: f32, world_time: f32) {
+        for i in 0..self.systems.len() {
+            let system = self.systems[i];
+            system(self, time_step, world_time);
         }
     }
 }
 
 pub struct Sprite {
     pos: Vec2,
+    /// The direction the sprite is facing, used to pick the frame of directional textures.
+    pub rot: f32,
     pub y_pos: f32,
-    pub texture: Texture,
+    pub animation: Animation,
     size: f32,
 }
 
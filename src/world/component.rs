@@ -0,0 +1,256 @@
+use super::EntityId;
+
+/// Where the components of one type are kept.
+pub trait Storage<T>: Default {
+    /// Gives the entity the component, and returns the one it had before.
+    fn insert(&mut self, id: EntityId, component: T) -> Option<T>;
+    fn remove(&mut self, id: EntityId) -> Option<T>;
+    fn get(&self, id: EntityId) -> Option<&T>;
+    fn get_mut(&mut self, id: EntityId) -> Option<&mut T>;
+}
+
+/// Goes through the components in a storage that is borrowed for `'a`. This is apart from
+/// `Storage`, since the iterators borrow the storage, so their types depend on the lifetime.
+pub trait StorageIter<'a, T: 'a> {
+    type Iter: Iterator<Item = (EntityId, &'a T)>;
+    type IterMut: Iterator<Item = (EntityId, &'a mut T)>;
+
+    fn iter(&'a self) -> Self::Iter;
+    fn iter_mut(&'a mut self) -> Self::IterMut;
+}
+
+/// Keeps the components in a list with a place for every entity. Good for components that
+/// most entities have, since lookups are just indexing, but iterating goes past the holes.
+pub struct DenseStorage<T> {
+    components: Vec<Option<T>>,
+}
+
+impl<T> Default for DenseStorage<T> {
+    fn default() -> Self {
+        Self {
+            components: Vec::new(),
+        }
+    }
+}
+
+impl<T> Storage<T> for DenseStorage<T> {
+    fn insert(&mut self, id: EntityId, component: T) -> Option<T> {
+        let index = id.into_index();
+        if index >= self.components.len() {
+            self.components.resize_with(index + 1, || None);
+        }
+        self.components[index].replace(component)
+    }
+
+    fn remove(&mut self, id: EntityId) -> Option<T> {
+        self.components.get_mut(id.into_index())?.take()
+    }
+
+    fn get(&self, id: EntityId) -> Option<&T> {
+        self.components.get(id.into_index())?.as_ref()
+    }
+
+    fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        self.components.get_mut(id.into_index())?.as_mut()
+    }
+}
+
+impl<'a, T: 'a> StorageIter<'a, T> for DenseStorage<T> {
+    type Iter = std::iter::FilterMap<
+        std::iter::Enumerate<std::slice::Iter<'a, Option<T>>>,
+        fn((usize, &'a Option<T>)) -> Option<(EntityId, &'a T)>,
+    >;
+    type IterMut = std::iter::FilterMap<
+        std::iter::Enumerate<std::slice::IterMut<'a, Option<T>>>,
+        fn((usize, &'a mut Option<T>)) -> Option<(EntityId, &'a mut T)>,
+    >;
+
+    fn iter(&'a self) -> Self::Iter {
+        self.components
+            .iter()
+            .enumerate()
+            .filter_map(|(index, component)| {
+                Some((EntityId::create_raw(index as u32), component.as_ref()?))
+            })
+    }
+
+    fn iter_mut(&'a mut self) -> Self::IterMut {
+        self.components
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, component)| {
+                Some((EntityId::create_raw(index as u32), component.as_mut()?))
+            })
+    }
+}
+
+/// Keeps the components packed together, with a list from entities to where their component
+/// is. Good for components that few entities have, since iterating only goes over those.
+pub struct SparseSet<T> {
+    /// The index in `components` for every entity, if it has one.
+    indices: Vec<Option<u32>>,
+    ids: Vec<EntityId>,
+    components: Vec<T>,
+}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> Self {
+        Self {
+            indices: Vec::new(),
+            ids: Vec::new(),
+            components: Vec::new(),
+        }
+    }
+}
+
+impl<T> Storage<T> for SparseSet<T> {
+    fn insert(&mut self, id: EntityId, component: T) -> Option<T> {
+        if let Some(old) = self.get_mut(id) {
+            return Some(std::mem::replace(old, component));
+        }
+
+        let index = id.into_index();
+        if index >= self.indices.len() {
+            self.indices.resize(index + 1, None);
+        }
+        self.indices[index] = Some(self.components.len() as u32);
+        self.ids.push(id);
+        self.components.push(component);
+        None
+    }
+
+    fn remove(&mut self, id: EntityId) -> Option<T> {
+        let index = self.indices.get_mut(id.into_index())?.take()? as usize;
+        // The last component takes the place of the removed one.
+        self.ids.swap_remove(index);
+        let component = self.components.swap_remove(index);
+        if let Some(moved) = self.ids.get(index) {
+            self.indices[moved.into_index()] = Some(index as u32);
+        }
+        Some(component)
+    }
+
+    fn get(&self, id: EntityId) -> Option<&T> {
+        let index = (*self.indices.get(id.into_index())?)?;
+        Some(&self.components[index as usize])
+    }
+
+    fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        let index = (*self.indices.get(id.into_index())?)?;
+        Some(&mut self.components[index as usize])
+    }
+}
+
+impl<'a, T: 'a> StorageIter<'a, T> for SparseSet<T> {
+    type Iter =
+        std::iter::Zip<std::iter::Copied<std::slice::Iter<'a, EntityId>>, std::slice::Iter<'a, T>>;
+    type IterMut = std::iter::Zip<
+        std::iter::Copied<std::slice::Iter<'a, EntityId>>,
+        std::slice::IterMut<'a, T>,
+    >;
+
+    fn iter(&'a self) -> Self::Iter {
+        self.ids.iter().copied().zip(self.components.iter())
+    }
+
+    fn iter_mut(&'a mut self) -> Self::IterMut {
+        self.ids.iter().copied().zip(self.components.iter_mut())
+    }
+}
+
+/// All components of one type, by the entity they belong to.
+pub struct Components<T, S = SparseSet<T>> {
+    storage: S,
+    _phantom: std::marker::PhantomData<T>,
+}
+
+impl<T, S: Storage<T>> Default for Components<T, S> {
+    fn default() -> Self {
+        Self {
+            storage: S::default(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T, S: Storage<T>> Components<T, S> {
+    pub fn insert(&mut self, id: EntityId, component: T) -> Option<T> {
+        self.storage.insert(id, component)
+    }
+
+    #[allow(unused)]
+    pub fn remove(&mut self, id: EntityId) -> Option<T> {
+        self.storage.remove(id)
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        self.storage.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        self.storage.get_mut(id)
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.storage.get(id).is_some()
+    }
+
+    pub fn iter<'a>(&'a self) -> <S as StorageIter<'a, T>>::Iter
+    where
+        S: StorageIter<'a, T>,
+    {
+        self.storage.iter()
+    }
+
+    pub fn iter_mut<'a>(&'a mut self) -> <S as StorageIter<'a, T>>::IterMut
+    where
+        S: StorageIter<'a, T>,
+    {
+        self.storage.iter_mut()
+    }
+
+    /// Goes through the entities that have this component and the ones in `other`, which can
+    /// be another set of components, or a tuple of them. This component can be changed.
+    pub fn join_mut<'a, J: Join + 'a>(
+        &'a mut self,
+        other: J,
+    ) -> impl Iterator<Item = (EntityId, &'a mut T, J::Item)> + 'a
+    where
+        S: StorageIter<'a, T>,
+    {
+        self.iter_mut()
+            .filter_map(move |(id, component)| Some((id, component, other.get(id)?)))
+    }
+}
+
+/// Components that can be looked up together for joined iteration.
+pub trait Join {
+    type Item;
+
+    /// Gets the components of the entity, if it has all of them.
+    fn get(&self, id: EntityId) -> Option<Self::Item>;
+}
+
+impl<'a, T, S: Storage<T>> Join for &'a Components<T, S> {
+    type Item = &'a T;
+
+    fn get(&self, id: EntityId) -> Option<Self::Item> {
+        self.storage.get(id)
+    }
+}
+
+impl<A: Join, B: Join> Join for (A, B) {
+    type Item = (A::Item, B::Item);
+
+    fn get(&self, id: EntityId) -> Option<Self::Item> {
+        Some((self.0.get(id)?, self.1.get(id)?))
+    }
+}
+
+impl<A: Join, B: Join, C: Join> Join for (A, B, C) {
+    type Item = (A::Item, B::Item, C::Item);
+
+    fn get(&self, id: EntityId) -> Option<Self::Item> {
+        Some((self.0.get(id)?, self.1.get(id)?, self.2.get(id)?))
+    }
+}
@@ -1,15 +1,20 @@
+use super::component::{Components, DenseStorage};
 use super::SpriteId;
 use crate::id::{Id, IdMap};
+use crate::texture::{Animation, Texture};
 use crate::Vec2;
-use std::collections::HashMap;
 
 create_id!(EntityId);
 
 #[derive(Default)]
 pub struct Entities {
     pub entities: IdMap<EntityId, Entity>,
-    pub transforms: HashMap<EntityId, Transform>,
-    pub evil_enemies: HashMap<EntityId, EvilEnemy>,
+    /// Almost every entity has a transform, so they're kept densely.
+    pub transforms: Components<Transform, DenseStorage<Transform>>,
+    pub evil_enemies: Components<EvilEnemy>,
+    pub animators: Components<Animator>,
+    pub healths: Components<Health>,
+    pub footsteps: Components<Footsteps>,
 }
 
 impl Entities {
@@ -32,6 +37,35 @@ pub struct Transform {
     pub sprite: Option<SpriteId>,
 }
 
+#[derive(Clone, Copy)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+}
+
+/// Makes an entity play footstep sounds while it's moving.
+pub struct Footsteps {
+    /// The time between two footsteps.
+    pub interval: f32,
+    /// The time until the next footstep.
+    pub timer: f32,
+}
+
+impl Footsteps {
+    pub fn new(interval: f32) -> Self {
+        Self {
+            interval,
+            timer: 0.0,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Entity {
     pub can_open_doors: bool,
@@ -39,6 +73,46 @@ pub struct Entity {
 
 pub enum EvilEnemy {
     Wander(f32),
-    #[allow(unused)]
+    /// Chasing something with health that it noticed.
     Angry(EntityId),
 }
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AnimationState {
+    Idle,
+    Walk,
+    Attack,
+}
+
+/// Picks the animation of the sprite of an entity based on what the entity is doing.
+pub struct Animator {
+    pub state: AnimationState,
+    pub idle: Texture,
+    pub walk: Texture,
+    pub attack: Texture,
+}
+
+impl Animator {
+    pub fn with_states(idle: Texture, walk: Texture, attack: Texture) -> Self {
+        Animator {
+            state: AnimationState::Idle,
+            idle,
+            walk,
+            attack,
+        }
+    }
+
+    pub fn animation(&self, start_time: f32) -> Animation {
+        match self.state {
+            AnimationState::Idle => Animation {
+                speed: 0.5,
+                ..Animation::new_loop_with_time(self.idle, start_time)
+            },
+            AnimationState::Walk => Animation::new_loop_with_time(self.walk, start_time),
+            AnimationState::Attack => Animation {
+                speed: 2.0,
+                ..Animation::new_loop_with_time(self.attack, start_time)
+            },
+        }
+    }
+}
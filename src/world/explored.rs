@@ -0,0 +1,95 @@
+use crate::bitset::BitSet;
+use std::io::{Read, Write};
+
+/// The tiles of the map that the player has seen, stored row by row.
+pub struct ExploredTiles {
+    tiles: BitSet,
+    width: usize,
+    height: usize,
+}
+
+impl ExploredTiles {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            tiles: BitSet::new(width * height),
+            width,
+            height,
+        }
+    }
+
+    /// The index of a tile in a bitset of explored tiles, if the tile is on the map.
+    #[inline]
+    pub fn index(&self, x: isize, y: isize) -> Option<usize> {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            Some(y as usize * self.width + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// The number of bits a bitset needs to be merged with `merge`.
+    pub fn n_tiles(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn is_explored(&self, x: isize, y: isize) -> bool {
+        matches!(self.index(x, y), Some(index) if self.tiles.contains(index))
+    }
+
+    /// Marks all the tiles in the bitset as explored, where the bitset is indexed by `index`.
+    pub fn merge(&mut self, tiles: &BitSet) {
+        self.tiles.union_with(tiles);
+    }
+
+    pub fn n_explored(&self) -> usize {
+        self.tiles.count()
+    }
+
+    /// Writes the explored tiles to a file, so that they can be loaded again
+    /// once the same world is generated. The layout is `TileMap::layout_hash`
+    /// of the map they were explored on.
+    pub fn save(&self, path: &str, layout: u64) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        file.write_all(&(self.width as u32).to_le_bytes())?;
+        file.write_all(&(self.height as u32).to_le_bytes())?;
+        file.write_all(&layout.to_le_bytes())?;
+        file.write_all(&self.tiles.to_bytes())?;
+        Ok(())
+    }
+
+    /// Loads the explored tiles written by `save`. The map has to be the same size,
+    /// and have the same layout, as when they were saved.
+    pub fn load(
+        path: &str,
+        width: usize,
+        height: usize,
+        layout: u64,
+    ) -> Result<Self, &'static str> {
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|_| "Couldn't load file")?;
+
+        if bytes.len() < 16 {
+            return Err("File is too short");
+        }
+        let (header, bits) = bytes.split_at(16);
+        let saved_width = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let saved_height = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if (saved_width as usize, saved_height as usize) != (width, height) {
+            return Err("The explored tiles are for a map of a different size");
+        }
+        let mut saved_layout = [0; 8];
+        saved_layout.copy_from_slice(&header[8..16]);
+        if u64::from_le_bytes(saved_layout) != layout {
+            return Err("The explored tiles are for a different map");
+        }
+
+        let tiles = BitSet::from_bytes(bits, width * height).ok_or("Wrong number of tiles")?;
+        Ok(Self {
+            tiles,
+            width,
+            height,
+        })
+    }
+}
@@ -1,11 +1,27 @@
-use super::{entity, Entities, Entity, EntityId, Tile, TileKind, TileMap, Transform, World};
-use crate::id::IdMap;
+use super::{
+    entity, Animator, Entity, EntityId, Footsteps, Health, Tile, TileKind, TileMap, Transform,
+    World,
+};
 use crate::random::Random;
 use crate::texture::Texture;
 use crate::Vec2;
+use std::collections::HashMap;
 
-const ROOM_WIDTH: usize = 4;
-const ROOM_HEIGHT: usize = 4;
+mod bsp;
+mod caves;
+mod decorate;
+mod graph;
+mod validate;
+
+pub use bsp::BspGenerator;
+pub use caves::CaveGenerator;
+pub use decorate::Decorations;
+
+pub use graph::{PlacedRoom, RoomGraph};
+
+/// The size of a room of a prefab, in tiles.
+pub const ROOM_WIDTH: usize = 4;
+pub const ROOM_HEIGHT: usize = 4;
 
 #[derive(Clone)]
 pub enum GenTileKind {
@@ -15,6 +31,23 @@ pub enum GenTileKind {
     Door,
 }
 
+impl GenTileKind {
+    /// If something can walk on the tile.
+    fn is_passable(&self) -> bool {
+        matches!(self, GenTileKind::Floor | GenTileKind::Door)
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "floor" => Some(GenTileKind::Floor),
+            "wall" => Some(GenTileKind::Wall),
+            "window" => Some(GenTileKind::Window),
+            "door" => Some(GenTileKind::Door),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct GenTile {
     pub kind: GenTileKind,
@@ -38,7 +71,32 @@ impl GenTile {
 pub struct WorldGenerator {
     n_rooms_width: usize,
     n_rooms_height: usize,
+    prefabs_path: String,
     prefabs: Vec<RoomPrefab>,
+    /// The prefab used when nothing else fits, it's one room big.
+    fallback_prefab: usize,
+    /// The chance of connecting to a room that is already there, when growing the dungeon
+    /// runs into it. More of these make more loops.
+    loopiness: f32,
+    /// The rooms that can be placed on, for every room in the world. The rest is solid rock.
+    mask: Vec<bool>,
+}
+
+/// A side of a room that hasn't been connected to anything yet.
+struct LooseEnd {
+    from_x: isize,
+    from_y: isize,
+    direction: Direction,
+    /// The depth of the room the loose end is on.
+    depth: usize,
+}
+
+/// What the placed prefabs have added so far, while the level grows.
+#[derive(Default)]
+struct GrowState {
+    loose_ends: Vec<LooseEnd>,
+    entities: Vec<(Vec2, GenEntity)>,
+    placed: Vec<PlacedRoom>,
 }
 
 impl WorldGenerator {
@@ -46,72 +104,296 @@ impl WorldGenerator {
         n_rooms_width: usize,
         n_rooms_height: usize,
         prefabs_path: &str,
-    ) -> Result<Self, &'static str> {
+    ) -> Result<Self, Vec<PrefabError>> {
+        let mut prefabs = load_prefabs_from_path(prefabs_path)?;
+
+        // The variants share the chance of the prefab, so that it isn't picked more often
+        // for having more of them.
+        for id in 0..prefabs.len() {
+            let mut variants = prefabs[id].make_variants(id);
+            let chance = prefabs[id].chance / (variants.len() + 1) as f32;
+            prefabs[id].chance = chance;
+            for variant in variants.iter_mut() {
+                variant.chance = chance;
+            }
+            prefabs.extend(variants);
+        }
+
+        let fallback_prefab = prefabs
+            .iter()
+            .position(|prefab| {
+                prefab.n_rooms_width == 1
+                    && prefab.n_rooms_height == 1
+                    && !prefab.has_tag(RoomTag::Start)
+            })
+            .ok_or_else(|| {
+                vec![PrefabError {
+                    file: prefabs_path.to_string(),
+                    line: 0,
+                    column: 0,
+                    text: String::new(),
+                    prefab: None,
+                    message: "There has to be a prefab that is a single room",
+                }]
+            })?;
+
         Ok(Self {
             n_rooms_width,
             n_rooms_height,
-            prefabs: load_prefabs_from_path(prefabs_path)?,
+            prefabs_path: prefabs_path.to_string(),
+            prefabs,
+            fallback_prefab,
+            loopiness: 0.05,
+            mask: vec![true; n_rooms_width * n_rooms_height],
         })
     }
 
-    pub fn generate(&self, random: &mut Random, start: Vec2) -> (EntityId, World) {
-        struct LooseEnd {
-            from_x: isize,
-            from_y: isize,
-            direction: Direction,
+    pub fn with_loopiness(mut self, loopiness: f32) -> Self {
+        self.loopiness = loopiness;
+        self
+    }
+
+    /// Only places rooms where `shape` returns true for the position of the room.
+    pub fn with_mask(mut self, shape: impl Fn(usize, usize) -> bool) -> Self {
+        for y in 0..self.n_rooms_height {
+            for x in 0..self.n_rooms_width {
+                self.mask[y * self.n_rooms_width + x] = shape(x, y);
+            }
+        }
+        self
+    }
+
+    /// Only places rooms where the image is bright. The image is stretched over the world,
+    /// so a pixel per room is the most detailed it gets.
+    pub fn with_mask_image(self, path: &str) -> Result<Self, &'static str> {
+        let image = image::open(path)
+            .map_err(|_| "Couldn't load mask image")?
+            .into_luma8();
+        let (width, height) = (self.n_rooms_width, self.n_rooms_height);
+        Ok(self.with_mask(|x, y| {
+            let pixel_x = (x * image.width() as usize / width) as u32;
+            let pixel_y = (y * image.height() as usize / height) as u32;
+            image.get_pixel(pixel_x, pixel_y)[0] >= 128
+        }))
+    }
+
+    /// The kind of a tile in a room, with the openings of the room applied.
+    fn tile_kind_at(&self, room: &Room, tile_x: usize, tile_y: usize) -> &GenTileKind {
+        let prefab = &self.prefabs[room.room_id];
+        let gen_tile =
+            &prefab.tiles[(room.room_y * ROOM_HEIGHT + tile_y) * ROOM_WIDTH * prefab.n_rooms_width
+                + room.room_x * ROOM_WIDTH
+                + tile_x];
+
+        match &gen_tile.change_if {
+            Some((new_kind, change_if_dir)) if room.get_dir(*change_if_dir) => new_kind,
+            _ => &gen_tile.kind,
+        }
+    }
+
+    /// Picks one of the prefabs that `filter` accepts, weighted by their chance.
+    fn pick_prefab(
+        &self,
+        random: &mut Random,
+        filter: impl Fn(usize, &RoomPrefab) -> bool,
+    ) -> Option<usize> {
+        let total_prefab_chance: f32 = self
+            .prefabs
+            .iter()
+            .enumerate()
+            .filter(|&(id, prefab)| filter(id, prefab))
+            .map(|(_, prefab)| prefab.chance)
+            .sum();
+        if total_prefab_chance <= 0.0 {
+            return None;
+        }
+
+        let mut room_we_want = random.get_float() * total_prefab_chance;
+        let mut last = None;
+        for (i, prefab) in self.prefabs.iter().enumerate() {
+            if !filter(i, prefab) {
+                continue;
+            }
+
+            room_we_want -= prefab.chance;
+            last = Some(i);
+            if room_we_want <= 0.0 {
+                break;
+            }
         }
+        last
+    }
 
-        let mut loose_ends = vec![
-            LooseEnd {
-                from_x: (start.x.floor() / ROOM_WIDTH as f32) as isize,
-                from_y: (start.y.floor() / ROOM_HEIGHT as f32) as isize,
+    /// Fills the rooms the prefab covers, and adds its entities and loose ends.
+    fn place_prefab(
+        &self,
+        rooms: &mut Rooms,
+        prefab_id: usize,
+        room_x: isize,
+        room_y: isize,
+        depth: usize,
+        state: &mut GrowState,
+    ) {
+        let GrowState {
+            loose_ends,
+            entities,
+            placed,
+        } = state;
+        let prefab = &self.prefabs[prefab_id];
+        for prefab_y in 0..prefab.n_rooms_height {
+            for prefab_x in 0..prefab.n_rooms_width {
+                let room = rooms
+                    .get_mut(room_x + prefab_x as isize, room_y + prefab_y as isize)
+                    .unwrap();
+
+                *room = Some(Room::new(prefab_id, prefab_x, prefab_y, placed.len()));
+            }
+        }
+        placed.push(PlacedRoom {
+            name: prefab.name.clone(),
+            tags: prefab.tags.clone(),
+            x: room_x,
+            y: room_y,
+            width: prefab.n_rooms_width,
+            height: prefab.n_rooms_height,
+            depth,
+            distance: None,
+            connections: Vec::new(),
+        });
+
+        for &(pos, ref entity_kind) in prefab.entities.iter() {
+            entities.push((
+                pos + Vec2::new(
+                    room_x as f32 * ROOM_WIDTH as f32,
+                    room_y as f32 * ROOM_HEIGHT as f32,
+                ),
+                entity_kind.clone(),
+            ));
+        }
+
+        for prefab_y in 0..prefab.n_rooms_height {
+            loose_ends.push(LooseEnd {
+                from_x: room_x,
+                from_y: room_y + prefab_y as isize,
                 direction: Direction::Left,
-            },
-            LooseEnd {
-                from_x: (start.x.floor() / ROOM_WIDTH as f32) as isize,
-                from_y: (start.y.floor() / ROOM_HEIGHT as f32) as isize,
-                direction: Direction::Down,
-            },
-            LooseEnd {
-                from_x: (start.x.floor() / ROOM_WIDTH as f32) as isize,
-                from_y: (start.y.floor() / ROOM_HEIGHT as f32) as isize,
-                direction: Direction::Up,
-            },
-            LooseEnd {
-                from_x: (start.x.floor() / ROOM_WIDTH as f32) as isize,
-                from_y: (start.y.floor() / ROOM_HEIGHT as f32) as isize,
+                depth,
+            });
+
+            loose_ends.push(LooseEnd {
+                from_x: room_x + prefab.n_rooms_width as isize - 1,
+                from_y: room_y + prefab_y as isize,
                 direction: Direction::Right,
-            },
-        ];
-        let mut rooms = Rooms::new(self.n_rooms_width, self.n_rooms_height);
-        *rooms
-            .get_mut(
-                (start.x.floor() / ROOM_WIDTH as f32) as isize,
-                (start.y.floor() / ROOM_HEIGHT as f32) as isize,
-            )
-            .unwrap() = Some(Room::new(0, 0, 0));
+                depth,
+            });
+        }
 
-        let total_prefab_chance: f32 = self.prefabs.iter().map(|v| v.chance).sum();
+        for prefab_x in 0..prefab.n_rooms_width {
+            loose_ends.push(LooseEnd {
+                from_x: room_x + prefab_x as isize,
+                from_y: room_y,
+                direction: Direction::Up,
+                depth,
+            });
+
+            loose_ends.push(LooseEnd {
+                from_x: room_x + prefab_x as isize,
+                from_y: room_y + prefab.n_rooms_height as isize - 1,
+                direction: Direction::Down,
+                depth,
+            });
+        }
+    }
+}
+
+/// Grows the level one room at a time out of the prefabs, starting from the start room. If
+/// the player wouldn't start inside the mask, they start in the middle of the room closest to
+/// where they would have.
+impl LevelGenerator for WorldGenerator {
+    fn generate_level(&self, random: &mut Random, start: Vec2) -> GeneratedLevel {
+        let start_x = (start.x.floor() / ROOM_WIDTH as f32) as isize;
+        let start_y = (start.y.floor() / ROOM_HEIGHT as f32) as isize;
+
+        let mut rooms = Rooms::new(self.n_rooms_width, self.n_rooms_height, self.mask.clone());
+
+        let mut start = start;
+        if !rooms.square_is_empty(start_x, start_y, 1, 1) {
+            // Rooms outside the mask are only picked if the mask doesn't allow any.
+            let distance = |index: usize| {
+                let (x, y) = (index % rooms.width, index / rooms.width);
+                let distance = (x as isize - start_x).pow(2) + (y as isize - start_y).pow(2);
+                (!rooms.mask[index], distance)
+            };
+            if let Some(index) = (0..rooms.mask.len()).min_by_key(|&index| distance(index)) {
+                start = Vec2::new(
+                    ((index % rooms.width) * ROOM_WIDTH + ROOM_WIDTH / 2) as f32 + 0.5,
+                    ((index / rooms.width) * ROOM_HEIGHT + ROOM_HEIGHT / 2) as f32 + 0.5,
+                );
+            }
+        }
+        let start_x = (start.x.floor() / ROOM_WIDTH as f32) as isize;
+        let start_y = (start.y.floor() / ROOM_HEIGHT as f32) as isize;
+
+        let mut prefab_counts = vec![0; self.prefabs.len()];
+        let mut state = GrowState::default();
+
+        let start_prefab = self.pick_prefab(random, |_, prefab| prefab.has_tag(RoomTag::Start));
+        match start_prefab {
+            Some(prefab_id)
+                if rooms.square_is_empty(
+                    start_x,
+                    start_y,
+                    self.prefabs[prefab_id].n_rooms_width as isize,
+                    self.prefabs[prefab_id].n_rooms_height as isize,
+                ) =>
+            {
+                prefab_counts[self.prefabs[prefab_id].source(prefab_id)] += 1;
+                self.place_prefab(&mut rooms, prefab_id, start_x, start_y, 0, &mut state);
+            }
+            _ => {
+                // Without a start room the player starts in an empty room, open on every side.
+                *rooms.get_mut(start_x, start_y).unwrap() =
+                    Some(Room::new(self.fallback_prefab, 0, 0, 0));
+                state.placed.push(PlacedRoom {
+                    name: self.prefabs[self.fallback_prefab].name.clone(),
+                    tags: Vec::new(),
+                    x: start_x,
+                    y: start_y,
+                    width: 1,
+                    height: 1,
+                    depth: 0,
+                    distance: None,
+                    connections: Vec::new(),
+                });
+                for &direction in &[
+                    Direction::Left,
+                    Direction::Down,
+                    Direction::Up,
+                    Direction::Right,
+                ] {
+                    state.loose_ends.push(LooseEnd {
+                        from_x: start_x,
+                        from_y: start_y,
+                        direction,
+                        depth: 0,
+                    });
+                }
+            }
+        }
 
-        let mut entities = Vec::new();
         let mut empty_spots = Vec::new();
-        'main_generator: while !loose_ends.is_empty() {
-            let loose_end_id = random.get_32() as usize % loose_ends.len();
-            let loose_end = loose_ends.swap_remove(loose_end_id);
+        'main_generator: while !state.loose_ends.is_empty() {
+            let loose_end_id = random.get_32() as usize % state.loose_ends.len();
+            let loose_end = state.loose_ends.swap_remove(loose_end_id);
 
             let (off_x, off_y) = loose_end.direction.to_vec();
+            let depth = loose_end.depth + 1;
 
-            let mut room_we_want = random.get_float() * total_prefab_chance;
-            let mut wanted_prefab_id = 0;
-            for (i, prefab) in self.prefabs.iter().enumerate() {
-                room_we_want -= prefab.chance;
-
-                if room_we_want <= 0.0 {
-                    wanted_prefab_id = i;
-                    break;
-                }
-            }
-            let mut wanted_prefab = &self.prefabs[wanted_prefab_id];
+            let mut wanted_prefab_id = self
+                .pick_prefab(random, |id, prefab| {
+                    prefab.can_place(depth, prefab_counts[prefab.source(id)])
+                })
+                .unwrap_or(self.fallback_prefab);
+            let wanted_prefab = &self.prefabs[wanted_prefab_id];
 
             empty_spots.clear();
             rooms.find_empty_spot(
@@ -131,14 +413,11 @@ impl WorldGenerator {
                         1,
                         1,
                     ) {
-                        if random.get_float() < 0.05 {
-                            if let Some(room) =
+                        if random.get_float() < self.loopiness {
+                            if let Some(Some(room)) =
                                 rooms.get_mut(loose_end.from_x + off_x, loose_end.from_y + off_y)
                             {
-                                *room
-                                    .as_mut()
-                                    .unwrap()
-                                    .get_dir_mut(loose_end.direction.inverted()) = true;
+                                *room.get_dir_mut(loose_end.direction.inverted()) = true;
                                 *rooms
                                     .get_mut(loose_end.from_x, loose_end.from_y)
                                     .unwrap()
@@ -150,8 +429,8 @@ impl WorldGenerator {
                         continue 'main_generator;
                     }
 
-                    wanted_prefab_id = 0;
-                    wanted_prefab = &self.prefabs[wanted_prefab_id];
+                    // The fallback fills gaps, so it doesn't care about depth or limits.
+                    wanted_prefab_id = self.fallback_prefab;
                     (loose_end.from_x + off_x, loose_end.from_y + off_y)
                 }
                 _ => {
@@ -160,25 +439,15 @@ impl WorldGenerator {
                 }
             };
 
-            for prefab_y in 0..wanted_prefab.n_rooms_height {
-                for prefab_x in 0..wanted_prefab.n_rooms_width {
-                    let room = rooms
-                        .get_mut(room_x + prefab_x as isize, room_y + prefab_y as isize)
-                        .unwrap();
-
-                    *room = Some(Room::new(wanted_prefab_id, prefab_x, prefab_y));
-                }
-            }
-
-            for &(pos, ref entity_kind) in wanted_prefab.entities.iter() {
-                entities.push((
-                    pos + Vec2::new(
-                        room_x as f32 * ROOM_WIDTH as f32,
-                        room_y as f32 * ROOM_HEIGHT as f32,
-                    ),
-                    entity_kind.clone(),
-                ));
-            }
+            prefab_counts[self.prefabs[wanted_prefab_id].source(wanted_prefab_id)] += 1;
+            self.place_prefab(
+                &mut rooms,
+                wanted_prefab_id,
+                room_x,
+                room_y,
+                depth,
+                &mut state,
+            );
 
             *rooms
                 .get_mut(loose_end.from_x + off_x, loose_end.from_y + off_y)
@@ -192,157 +461,115 @@ impl WorldGenerator {
                 .as_mut()
                 .unwrap()
                 .get_dir_mut(loose_end.direction) = true;
-
-            for prefab_y in 0..wanted_prefab.n_rooms_height {
-                loose_ends.push(LooseEnd {
-                    from_x: room_x,
-                    from_y: room_y + prefab_y as isize,
-                    direction: Direction::Left,
-                });
-
-                loose_ends.push(LooseEnd {
-                    from_x: room_x + wanted_prefab.n_rooms_width as isize - 1,
-                    from_y: room_y + prefab_y as isize,
-                    direction: Direction::Right,
-                });
-            }
-
-            for prefab_x in 0..wanted_prefab.n_rooms_width {
-                loose_ends.push(LooseEnd {
-                    from_x: room_x + prefab_x as isize,
-                    from_y: room_y,
-                    direction: Direction::Up,
-                });
-
-                loose_ends.push(LooseEnd {
-                    from_x: room_x + prefab_x as isize,
-                    from_y: room_y + wanted_prefab.n_rooms_height as isize - 1,
-                    direction: Direction::Down,
-                });
-            }
-        }
-
-        let mut world = World {
-            tiles: TileMap::new(
-                vec![
-                    Tile::new(TileKind::Floor);
-                    self.n_rooms_width * self.n_rooms_height * ROOM_WIDTH * ROOM_HEIGHT
-                ],
-                self.n_rooms_width * ROOM_WIDTH,
-                self.n_rooms_height * ROOM_HEIGHT,
-            ),
-            random: Random::new(),
-            sprites: IdMap::new(),
-            entities: Entities::new(),
-        };
-
-        eprintln!("Generating {} entities", entities.len());
-        for (pos, entity) in entities {
-            match entity {
-                GenEntity::Evil => {
-                    let id = world.entities.insert(Default::default());
-                    let sprite_id = world.insert_sprite(Texture::Evil, pos, 0.4, 0.5);
-                    world.entities.transforms.insert(
-                        id,
-                        Transform {
-                            pos,
-                            drag: 0.4,
-                            size: 0.2,
-                            sprite: Some(sprite_id),
-                            ..Default::default()
-                        },
-                    );
-                    world
-                        .entities
-                        .evil_enemies
-                        .insert(id, entity::EvilEnemy::Wander(0.0));
-                }
-            }
         }
 
+        // The start room is always the first one placed.
+        let mut connections = self.room_connections(&rooms, state.placed.len());
+        let added_connections = self.connect_all_rooms(&mut rooms, &mut connections, 0);
+        let removed = self.remove_unreachable_rooms(&mut rooms, &mut state, &mut connections, 0);
+        let room_graph = RoomGraph::new(state.placed, connections, 0, added_connections, removed);
+        eprintln!("{}", room_graph.stats);
+
+        let mut tiles = TileMap::new(
+            vec![
+                Tile::new(TileKind::Floor);
+                self.n_rooms_width * self.n_rooms_height * ROOM_WIDTH * ROOM_HEIGHT
+            ],
+            self.n_rooms_width * ROOM_WIDTH,
+            self.n_rooms_height * ROOM_HEIGHT,
+        );
         for (room_y, chunk) in rooms.rooms.chunks(self.n_rooms_width).enumerate() {
             for (room_x, room) in chunk.iter().enumerate() {
-                let room = room.as_ref().expect("Didn't fill the entire dungeon!?");
-
-                let room_data = &self.prefabs[room.room_id];
-
                 for tile_y in 0..ROOM_HEIGHT {
                     for tile_x in 0..ROOM_WIDTH {
-                        let gen_tile = &room_data.tiles[(room.room_y * ROOM_HEIGHT + tile_y)
-                            * ROOM_WIDTH
-                            * room_data.n_rooms_width
-                            + room.room_x * ROOM_WIDTH
-                            + tile_x];
-
-                        let mut gen_tile_kind = &gen_tile.kind;
-                        if let &Some((ref new_kind, change_if_dir)) = &gen_tile.change_if {
-                            if room.get_dir(change_if_dir) {
-                                gen_tile_kind = &new_kind;
-                            }
-                        }
-
-                        let kind = match gen_tile_kind {
-                            GenTileKind::Door => TileKind::Door(false),
-                            GenTileKind::Floor => {
-                                if random.get_float() <= 0.001 {
-                                    let pos = Vec2::new(
-                                        (room_x * ROOM_WIDTH + tile_x) as f32 + 0.5,
-                                        (room_y * ROOM_HEIGHT + tile_y) as f32 + 0.5,
-                                    );
-                                    let sprite = world.insert_sprite(Texture::Rick, pos, 1.0, 0.0);
-                                    let entity_id = world.entities.insert(Entity {
-                                        can_open_doors: false,
-                                    });
-                                    world.entities.transforms.insert(
-                                        entity_id,
-                                        Transform {
-                                            drag: 0.0,
-                                            vel: Vec2::new(
-                                                (random.get_float() - 0.5) * 25.0,
-                                                (random.get_float() - 0.5) * 25.0,
-                                            ),
-                                            pos,
-                                            size: 0.3,
-                                            sprite: Some(sprite),
-                                            ..Default::default()
-                                        },
-                                    );
-                                }
-                                for _ in 0..(random.get_float()
-                                    * random.get_float()
-                                    * random.get_float()
-                                    * 60.0) as u32
-                                {
-                                    let pos = Vec2::new(
-                                        (room_x * ROOM_WIDTH + tile_x) as f32 + random.get_float(),
-                                        (room_y * ROOM_HEIGHT + tile_y) as f32 + random.get_float(),
-                                    );
-                                    world.insert_sprite(
-                                        Texture::Fungus,
-                                        pos,
-                                        random.get_float() * 0.1 + 0.1,
-                                        1.0,
-                                    );
-                                }
-                                TileKind::Floor
-                            }
-                            GenTileKind::Wall => TileKind::Wall,
-                            GenTileKind::Window => TileKind::Window,
+                        let kind = match room {
+                            Some(room) => match self.tile_kind_at(room, tile_x, tile_y) {
+                                GenTileKind::Door => TileKind::Door(false),
+                                GenTileKind::Floor => TileKind::Floor,
+                                GenTileKind::Wall => TileKind::Wall,
+                                GenTileKind::Window => TileKind::Window,
+                            },
+                            // Everything outside of the rooms is solid rock.
+                            None => TileKind::Wall,
                         };
 
-                        let tile = world
-                            .tiles
+                        tiles
                             .get_mut_usize(
                                 room_x * ROOM_WIDTH + tile_x,
                                 room_y * ROOM_HEIGHT + tile_y,
                             )
-                            .unwrap();
-                        tile.set_kind(kind);
+                            .unwrap()
+                            .set_kind(kind);
                     }
                 }
             }
         }
 
+        GeneratedLevel {
+            tiles,
+            entities: state.entities,
+            start,
+            rooms: Some(room_graph),
+        }
+    }
+}
+
+/// Makes the layout of a world, and where things spawn in it.
+pub trait LevelGenerator {
+    /// `start` is where the player should start, the generator may move it to somewhere
+    /// that fits the level better.
+    fn generate_level(&self, random: &mut Random, start: Vec2) -> GeneratedLevel;
+}
+
+/// The level generators that can be picked from the command line.
+#[derive(Clone, Copy)]
+pub enum GeneratorKind {
+    Prefabs,
+    Caves,
+    Bsp,
+    /// One of the others, picked at random for every level.
+    Any,
+}
+
+impl std::str::FromStr for GeneratorKind {
+    type Err = &'static str;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "prefabs" => Ok(GeneratorKind::Prefabs),
+            "caves" => Ok(GeneratorKind::Caves),
+            "bsp" => Ok(GeneratorKind::Bsp),
+            "any" => Ok(GeneratorKind::Any),
+            _ => Err("Unknown generator"),
+        }
+    }
+}
+
+/// The layout of a world, before anything has been spawned into it.
+pub struct GeneratedLevel {
+    pub tiles: TileMap,
+    pub entities: Vec<(Vec2, GenEntity)>,
+    /// Where the player starts, always on a floor tile.
+    pub start: Vec2,
+    /// How the rooms connect, for the generators that make rooms out of prefabs.
+    pub rooms: Option<RoomGraph>,
+}
+
+impl GeneratedLevel {
+    /// Spawns the entities and the player, and decorates the floor.
+    pub fn into_world(self, random: &mut Random, decorations: &Decorations) -> (EntityId, World) {
+        let mut world = World::new(self.tiles);
+
+        eprintln!("Generating {} entities", self.entities.len());
+        for (pos, entity) in self.entities {
+            match entity {
+                GenEntity::Evil => spawn_evil(&mut world, pos),
+                GenEntity::Rick => spawn_rick(&mut world, random, pos),
+            }
+        }
+
+        decorations.apply(&mut world, random, self.rooms.as_ref());
+
         let player_id = world.entities.insert(Entity {
             can_open_doors: true,
             ..Default::default()
@@ -350,17 +577,68 @@ impl WorldGenerator {
         world.entities.transforms.insert(
             player_id,
             Transform {
-                pos: start,
+                pos: self.start,
                 size: 0.2,
                 drag: 1.0,
                 ..Default::default()
             },
         );
+        world.entities.healths.insert(player_id, Health::new(100.0));
+        world
+            .entities
+            .footsteps
+            .insert(player_id, Footsteps::new(0.35));
 
         (player_id, world)
     }
 }
 
+fn spawn_evil(world: &mut World, pos: Vec2) {
+    let id = world.entities.insert(Default::default());
+    let animator = Animator::with_states(Texture::Evil, Texture::EvilWalk, Texture::EvilAttack);
+    let sprite_id = world.insert_sprite(animator.animation(0.0), pos, 0.4, 0.5);
+    world.entities.transforms.insert(
+        id,
+        Transform {
+            pos,
+            drag: 0.4,
+            size: 0.2,
+            sprite: Some(sprite_id),
+            ..Default::default()
+        },
+    );
+    world
+        .entities
+        .evil_enemies
+        .insert(id, entity::EvilEnemy::Wander(0.0));
+    world.entities.animators.insert(id, animator);
+    world.entities.footsteps.insert(id, Footsteps::new(0.5));
+}
+
+/// Rick bounces around the world, without ever slowing down.
+fn spawn_rick(world: &mut World, random: &mut Random, pos: Vec2) {
+    let animator = Animator::with_states(Texture::Rick, Texture::RickWalk, Texture::Rick);
+    let sprite = world.insert_sprite(animator.animation(0.0), pos, 1.0, 0.0);
+    let entity_id = world.entities.insert(Entity {
+        can_open_doors: false,
+    });
+    world.entities.transforms.insert(
+        entity_id,
+        Transform {
+            drag: 0.0,
+            vel: Vec2::new(
+                (random.get_float() - 0.5) * 25.0,
+                (random.get_float() - 0.5) * 25.0,
+            ),
+            pos,
+            size: 0.3,
+            sprite: Some(sprite),
+            ..Default::default()
+        },
+    );
+    world.entities.animators.insert(entity_id, animator);
+}
+
 // TODO: Move this into its own thing.
 #[derive(Clone, Copy, Debug)]
 pub enum Direction {
@@ -371,6 +649,25 @@ pub enum Direction {
 }
 
 impl Direction {
+    /// The direction after turning a quarter turn clockwise.
+    fn rotated(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Right,
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
+        }
+    }
+
+    /// The direction after flipping from left to right.
+    fn mirrored(self) -> Direction {
+        match self {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            direction => direction,
+        }
+    }
+
     fn inverted(self) -> Direction {
         match self {
             Direction::Up => Direction::Down,
@@ -391,23 +688,236 @@ impl Direction {
 }
 
 #[derive(Clone)]
-enum GenEntity {
+pub enum GenEntity {
     Evil,
+    Rick,
+}
+
+impl GenEntity {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "evil" => Some(GenEntity::Evil),
+            "rick" => Some(GenEntity::Rick),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RoomTag {
+    /// The room the player starts in.
+    Start,
+    Exit,
+    Treasure,
+}
+
+impl RoomTag {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "start" => Some(RoomTag::Start),
+            "exit" => Some(RoomTag::Exit),
+            "treasure" => Some(RoomTag::Treasure),
+            _ => None,
+        }
+    }
+}
+
+/// The ways a prefab can be turned and flipped when generating.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Variant {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Flipped from left to right, along with any of the rotations that are allowed.
+    Mirror,
 }
 
+impl Variant {
+    const ALL: [Variant; 4] = [
+        Variant::Rotate90,
+        Variant::Rotate180,
+        Variant::Rotate270,
+        Variant::Mirror,
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rotate90" => Some(Variant::Rotate90),
+            "rotate180" => Some(Variant::Rotate180),
+            "rotate270" => Some(Variant::Rotate270),
+            "mirror" => Some(Variant::Mirror),
+            _ => None,
+        }
+    }
+}
+
+/// What a two character code in a prefab turns into, from the legend section.
+#[derive(Clone)]
+struct LegendEntry {
+    tile: GenTileKind,
+    entity: Option<GenEntity>,
+}
+
+#[derive(Clone)]
 struct RoomPrefab {
+    name: String,
+    /// The line in the file the room started on.
+    line: usize,
+    /// The line and column of the first tile of every row, for pointing at tiles in errors.
+    rows: Vec<(usize, usize)>,
     entities: Vec<(Vec2, GenEntity)>,
     chance: f32,
+    tags: Vec<RoomTag>,
+    /// The depth is how many rooms away from the start room a room is.
+    min_depth: usize,
+    max_depth: usize,
+    /// The most times the prefab can be used in one world, counting all of its variants.
+    max_count: usize,
+    /// The variants that are made of the prefab.
+    variants: Vec<Variant>,
+    /// The prefab this is a variant of, if it's a variant.
+    variant_of: Option<usize>,
     tiles: Vec<GenTile>,
     n_rooms_width: usize,
     n_rooms_height: usize,
 }
 
+impl RoomPrefab {
+    fn new(name: String, line: usize) -> Self {
+        Self {
+            name,
+            line,
+            rows: Vec::new(),
+            entities: Vec::new(),
+            chance: 1.0,
+            tags: Vec::new(),
+            min_depth: 0,
+            max_depth: usize::MAX,
+            max_count: usize::MAX,
+            variants: Variant::ALL.to_vec(),
+            variant_of: None,
+            tiles: Vec::new(),
+            n_rooms_width: 0,
+            n_rooms_height: 0,
+        }
+    }
+
+    fn has_tag(&self, tag: RoomTag) -> bool {
+        self.tags.contains(&tag)
+    }
+
+    /// The prefab that limits on how often the prefab is used count towards.
+    fn source(&self, id: usize) -> usize {
+        self.variant_of.unwrap_or(id)
+    }
+
+    /// Makes a copy of the prefab with every tile moved by `move_tile`, which gets the
+    /// position of a tile and returns where it goes in a prefab that is `width` tiles wide.
+    fn transformed(
+        &self,
+        width: usize,
+        move_tile: impl Fn(usize, usize) -> (usize, usize),
+        move_entity: impl Fn(Vec2) -> Vec2,
+        turn: impl Fn(Direction) -> Direction,
+    ) -> Self {
+        let old_width = self.n_rooms_width * ROOM_WIDTH;
+        let mut tiles = self.tiles.clone();
+        for (i, tile) in self.tiles.iter().enumerate() {
+            let (x, y) = move_tile(i % old_width, i / old_width);
+            let mut tile = tile.clone();
+            if let Some((_, direction)) = &mut tile.change_if {
+                *direction = turn(*direction);
+            }
+            tiles[y * width + x] = tile;
+        }
+
+        Self {
+            rows: Vec::new(),
+            entities: self
+                .entities
+                .iter()
+                .map(|(pos, entity)| (move_entity(*pos), entity.clone()))
+                .collect(),
+            tiles,
+            ..self.clone()
+        }
+    }
+
+    /// The prefab turned a quarter turn clockwise.
+    fn rotated(&self) -> Self {
+        let height = (self.n_rooms_height * ROOM_HEIGHT) as f32;
+        let mut prefab = self.transformed(
+            self.n_rooms_height * ROOM_HEIGHT,
+            |x, y| (self.n_rooms_height * ROOM_HEIGHT - 1 - y, x),
+            |pos| Vec2::new(height - pos.y, pos.x),
+            Direction::rotated,
+        );
+        prefab.n_rooms_width = self.n_rooms_height;
+        prefab.n_rooms_height = self.n_rooms_width;
+        prefab
+    }
+
+    /// The prefab flipped from left to right.
+    fn mirrored(&self) -> Self {
+        let width = (self.n_rooms_width * ROOM_WIDTH) as f32;
+        self.transformed(
+            self.n_rooms_width * ROOM_WIDTH,
+            |x, y| (self.n_rooms_width * ROOM_WIDTH - 1 - x, y),
+            |pos| Vec2::new(width - pos.x, pos.y),
+            Direction::mirrored,
+        )
+    }
+
+    /// The rotated and mirrored copies of the prefab that it allows, not including itself.
+    fn make_variants(&self, id: usize) -> Vec<RoomPrefab> {
+        let rotations = [
+            None,
+            Some(Variant::Rotate90),
+            Some(Variant::Rotate180),
+            Some(Variant::Rotate270),
+        ];
+
+        let mut variants = Vec::new();
+        let mut rotated = self.clone();
+        for (quarters, rotation) in rotations.iter().enumerate() {
+            if quarters > 0 {
+                rotated = rotated.rotated();
+                rotated.name = format!("{} (rotated {})", self.name, quarters * 90);
+            }
+            rotated.variant_of = Some(id);
+
+            if let Some(rotation) = rotation {
+                if !self.variants.contains(rotation) {
+                    continue;
+                }
+                variants.push(rotated.clone());
+            }
+            if self.variants.contains(&Variant::Mirror) {
+                let mut mirrored = rotated.mirrored();
+                mirrored.name = format!("{} (mirrored)", rotated.name);
+                variants.push(mirrored);
+            }
+        }
+        variants
+    }
+
+    /// If the prefab can be picked at random for a room at the given depth.
+    fn can_place(&self, depth: usize, count: usize) -> bool {
+        !self.has_tag(RoomTag::Start)
+            && self.chance > 0.0
+            && depth >= self.min_depth
+            && depth <= self.max_depth
+            && count < self.max_count
+    }
+}
+
 #[derive(Clone)]
 struct Room {
     room_id: usize,
     room_x: usize,
     room_y: usize,
+    /// The index of the placed prefab the room is part of.
+    instance: usize,
     left: bool,
     right: bool,
     up: bool,
@@ -415,11 +925,12 @@ struct Room {
 }
 
 impl Room {
-    fn new(room_id: usize, room_x: usize, room_y: usize) -> Self {
+    fn new(room_id: usize, room_x: usize, room_y: usize, instance: usize) -> Self {
         Room {
             room_id,
             room_x,
             room_y,
+            instance,
             left: false,
             right: false,
             up: false,
@@ -448,14 +959,17 @@ impl Room {
 
 struct Rooms {
     rooms: Vec<Option<Room>>,
+    /// If a room can be placed there, for every room.
+    mask: Vec<bool>,
     width: usize,
     height: usize,
 }
 
 impl Rooms {
-    fn new(width: usize, height: usize) -> Self {
+    fn new(width: usize, height: usize, mask: Vec<bool>) -> Self {
         Self {
             rooms: vec![None; width * height],
+            mask,
             width,
             height,
         }
@@ -559,13 +1073,13 @@ impl Rooms {
         }
     }
 
-    /// Returns true if the square is inside the bounds of the rooms, and if there are no
-    /// rooms there already.
+    /// Returns true if the square is inside the bounds and the mask of the rooms, and if
+    /// there are no rooms there already.
     fn square_is_empty(&self, x: isize, y: isize, width: isize, height: isize) -> bool {
         for y in y..y + height {
             for x in x..x + width {
                 match self.get(x, y) {
-                    Some(None) => (),
+                    Some(None) if self.mask[y as usize * self.width + x as usize] => (),
                     _ => return false,
                 }
             }
@@ -575,7 +1089,135 @@ impl Rooms {
     }
 }
 
-fn load_prefabs_from_path(path: &str) -> Result<Vec<RoomPrefab>, &'static str> {
+/// A problem in a room prefab file.
+#[derive(Debug)]
+pub struct PrefabError {
+    pub file: String,
+    /// Starts at 1, 0 means that the problem is with the whole file.
+    pub line: usize,
+    pub column: usize,
+    /// The characters that caused the problem, empty if there aren't any in particular.
+    pub text: String,
+    /// The room the problem is in, if it's in one.
+    pub prefab: Option<String>,
+    pub message: &'static str,
+}
+
+impl std::fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)?;
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, self.line, self.column, self.message
+            )?;
+        }
+        if !self.text.is_empty() {
+            write!(f, " '{}'", self.text)?;
+        }
+        if let Some(prefab) = &self.prefab {
+            write!(f, " in room '{}'", prefab)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the problems in a prefab file, so that all of them can be reported at once.
+struct PrefabErrors<'a> {
+    file: &'a str,
+    errors: Vec<PrefabError>,
+}
+
+impl PrefabErrors<'_> {
+    fn push(
+        &mut self,
+        line: usize,
+        column: usize,
+        text: &str,
+        prefab: Option<&RoomPrefab>,
+        message: &'static str,
+    ) {
+        self.errors.push(PrefabError {
+            file: self.file.to_string(),
+            line,
+            column,
+            text: text.to_string(),
+            prefab: prefab
+                .filter(|prefab| !prefab.name.is_empty())
+                .map(|prefab| prefab.name.clone()),
+            message,
+        });
+    }
+}
+
+/// The words of a line, with the byte offset they start at.
+fn words(line: &str) -> impl Iterator<Item = (usize, &str)> + Clone {
+    line.split_whitespace()
+        .map(move |word| (word.as_ptr() as usize - line.as_ptr() as usize, word))
+}
+
+/// The column of a byte offset in a line, starting at 1.
+fn column(line: &str, offset: usize) -> usize {
+    line[..offset].chars().count() + 1
+}
+
+/// A problem with a word on a line: the byte offset it starts at, the word, and the message.
+type ParseError<'a> = (usize, &'a str, &'static str);
+
+/// Parses the value of a property, and on failure returns the word that's wrong.
+fn value<'a, T: std::str::FromStr>(
+    value: Option<(usize, &'a str)>,
+    line_end: usize,
+    missing: &'static str,
+    invalid: &'static str,
+) -> Result<T, ParseError<'a>> {
+    let (offset, value) = value.ok_or((line_end, "", missing))?;
+    value.parse().map_err(|_| (offset, value, invalid))
+}
+
+/// Loads the room prefabs. A file is made of sections, each starting with a header line.
+///
+/// `-- <name>` starts a room, followed by `- <property> <values>` lines and then the tiles,
+/// two characters per tile. The properties are `chance`, `tags` (`start`, `exit` and `treasure`),
+/// `min_depth`, `max_depth`, `max_count` and `variants` (`rotate90`, `rotate180`, `rotate270`
+/// and `mirror`, or `none`, all of them are made if it's not set).
+///
+/// `== legend` starts a legend, where every line is `<code> <tile> [entity]`, and makes the
+/// two character code usable in the rooms after it.
+///
+/// If there are problems, all of them are returned rather than just the first one.
+fn load_prefabs_from_path(path: &str) -> Result<Vec<RoomPrefab>, Vec<PrefabError>> {
+    let mut errors = PrefabErrors {
+        file: path,
+        errors: Vec::new(),
+    };
+
+    let file_contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => {
+            errors.push(0, 0, "", None, "Couldn't load file");
+            return Err(errors.errors);
+        }
+    };
+
+    let prefabs = parse_prefabs(&file_contents, &mut errors);
+    if errors.errors.is_empty() {
+        Ok(prefabs)
+    } else {
+        Err(errors.errors)
+    }
+}
+
+fn parse_prefabs(file_contents: &str, errors: &mut PrefabErrors<'_>) -> Vec<RoomPrefab> {
+    /// The room that is being parsed.
+    struct CurrentPrefab {
+        /// How many errors there were before the room started.
+        n_errors: usize,
+        prefab: RoomPrefab,
+    }
+
     fn validate_room_prefab(prefab: &RoomPrefab) -> Result<(), &'static str> {
         if prefab.n_rooms_width == 0 {
             return Err("Prefab width cannot be zero");
@@ -588,70 +1230,280 @@ fn load_prefabs_from_path(path: &str) -> Result<Vec<RoomPrefab>, &'static str> {
         {
             return Err("Prefab tiles do not match up with with and height of room(make sure that width and height are divisible by ROOM_WIDTH and ROOM_HEIGHT");
         }
+        if prefab.min_depth > prefab.max_depth {
+            return Err("'min_depth' is larger than 'max_depth'");
+        }
+
+        Ok(())
+    }
+
+    /// Adds the prefab if it's valid. The problems are reported at the line it started on.
+    /// Rooms that already had problems aren't checked, those problems usually cause these.
+    fn finish_prefab(
+        prefab: Option<CurrentPrefab>,
+        prefabs: &mut Vec<RoomPrefab>,
+        errors: &mut PrefabErrors<'_>,
+    ) {
+        if let Some(CurrentPrefab {
+            n_errors,
+            mut prefab,
+        }) = prefab
+        {
+            if errors.errors.len() > n_errors {
+                return;
+            }
+
+            prefab.n_rooms_height = prefab
+                .tiles
+                .len()
+                .checked_div(prefab.n_rooms_width * ROOM_WIDTH * ROOM_HEIGHT)
+                .unwrap_or(0);
+
+            match validate_room_prefab(&prefab) {
+                Ok(()) => prefabs.push(prefab),
+                Err(message) => errors.push(prefab.line, 1, "", Some(&prefab), message),
+            }
+        }
+    }
 
+    /// Parses a `- <property> <values>` line, and on failure returns the word that's wrong.
+    fn parse_property<'a>(
+        prefab: &mut RoomPrefab,
+        mut parts: impl Iterator<Item = (usize, &'a str)>,
+        line_end: usize,
+    ) -> Result<(), ParseError<'a>> {
+        let (offset, name) = parts
+            .next()
+            .ok_or((line_end, "", "Expected property name"))?;
+        match name {
+            "chance" => {
+                prefab.chance = value(
+                    parts.next(),
+                    line_end,
+                    "Expected float after 'chance'",
+                    "Float after 'chance' is incorrectly formatted",
+                )?;
+            }
+            "tags" => {
+                for (offset, tag) in parts {
+                    prefab.tags.push(RoomTag::from_name(tag).ok_or((
+                        offset,
+                        tag,
+                        "Unknown room tag",
+                    ))?);
+                }
+            }
+            "min_depth" => {
+                prefab.min_depth = value(
+                    parts.next(),
+                    line_end,
+                    "Expected integer after 'min_depth'",
+                    "Integer after 'min_depth' is incorrectly formatted",
+                )?;
+            }
+            "max_depth" => {
+                prefab.max_depth = value(
+                    parts.next(),
+                    line_end,
+                    "Expected integer after 'max_depth'",
+                    "Integer after 'max_depth' is incorrectly formatted",
+                )?;
+            }
+            "variants" => {
+                prefab.variants.clear();
+                for (offset, name) in parts {
+                    if name != "none" {
+                        prefab.variants.push(Variant::from_name(name).ok_or((
+                            offset,
+                            name,
+                            "Unknown variant",
+                        ))?);
+                    }
+                }
+            }
+            "max_count" => {
+                prefab.max_count = value(
+                    parts.next(),
+                    line_end,
+                    "Expected integer after 'max_count'",
+                    "Integer after 'max_count' is incorrectly formatted",
+                )?;
+            }
+            _ => return Err((offset, name, "Unknown property")),
+        }
         Ok(())
     }
 
-    let file_contents = std::fs::read_to_string(path).map_err(|_| "Couldn't load file")?;
+    /// Parses a `<code> <tile> [entity]` line, and on failure returns the word that's wrong.
+    fn parse_legend_entry<'a>(
+        mut parts: impl Iterator<Item = (usize, &'a str)>,
+        line_end: usize,
+    ) -> Result<((char, char), LegendEntry), ParseError<'a>> {
+        let (offset, code) = parts.next().unwrap();
+        let mut code_chars = code.chars();
+        let key = match (code_chars.next(), code_chars.next(), code_chars.next()) {
+            (Some(c), Some(modifier), None) => (c, modifier),
+            _ => return Err((offset, code, "Legend codes have to be two characters")),
+        };
+
+        let (offset, tile) = parts.next().ok_or((line_end, "", "Expected tile name"))?;
+        let tile = GenTileKind::from_name(tile).ok_or((offset, tile, "Unknown tile name"))?;
+        let entity = match parts.next() {
+            Some((offset, name)) => {
+                Some(GenEntity::from_name(name).ok_or((offset, name, "Unknown entity type"))?)
+            }
+            None => None,
+        };
+        Ok((key, LegendEntry { tile, entity }))
+    }
 
     let mut prefabs = Vec::new();
-    let mut current_prefab: Option<RoomPrefab> = None;
+    let mut legend: HashMap<(char, char), LegendEntry> = HashMap::new();
+    let mut current_prefab: Option<CurrentPrefab> = None;
+    let mut in_legend = false;
     let mut y = 0;
 
-    for line in file_contents
-        .lines()
-        .map(|line| line.trim())
-        .filter(|line| !line.is_empty())
-    {
-        let mut parts = line.split_whitespace();
-        match parts.next().unwrap() {
+    for (line_index, line) in file_contents.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line_end = line.trim_end().len();
+        let mut parts = words(line);
+        let (offset, first) = match parts.clone().next() {
+            Some(first) => first,
+            None => continue,
+        };
+
+        match first {
             "--" => {
-                if let Some(mut prefab) = current_prefab.take() {
-                    prefab.n_rooms_height = prefab
-                        .tiles
-                        .len()
-                        .checked_div(prefab.n_rooms_width * ROOM_WIDTH * ROOM_HEIGHT)
-                        .unwrap_or(0);
-
-                    validate_room_prefab(&prefab)?;
-                    prefabs.push(prefab);
+                finish_prefab(current_prefab.take(), &mut prefabs, errors);
+
+                let name = line.trim()[2..].trim();
+                let prefab = RoomPrefab::new(name.to_string(), line_number);
+                if name.is_empty() {
+                    errors.push(
+                        line_number,
+                        column(line, line_end),
+                        "",
+                        None,
+                        "Expected name of area",
+                    );
                 }
-
-                let _name = parts.next().ok_or("Expected name of area");
-                current_prefab = Some(RoomPrefab {
-                    entities: Vec::new(),
-                    chance: 0.0,
-                    tiles: Vec::new(),
-                    n_rooms_width: 0,
-                    n_rooms_height: 0,
+                current_prefab = Some(CurrentPrefab {
+                    n_errors: errors.errors.len(),
+                    prefab,
                 });
+                in_legend = false;
                 y = 0;
             }
+            "==" => {
+                finish_prefab(current_prefab.take(), &mut prefabs, errors);
+
+                parts.next();
+                match parts.next() {
+                    Some((_, "legend")) => in_legend = true,
+                    Some((offset, name)) => {
+                        errors.push(
+                            line_number,
+                            column(line, offset),
+                            name,
+                            None,
+                            "Unknown section",
+                        );
+                    }
+                    None => {
+                        errors.push(
+                            line_number,
+                            column(line, line_end),
+                            "",
+                            None,
+                            "Expected section name",
+                        );
+                    }
+                }
+            }
+            _ if in_legend => match parse_legend_entry(parts, line_end) {
+                Ok((key, entry)) => {
+                    legend.insert(key, entry);
+                }
+                Err((offset, text, message)) => {
+                    errors.push(line_number, column(line, offset), text, None, message);
+                }
+            },
             "-" => {
-                let prefab = current_prefab
-                    .as_mut()
-                    .ok_or("Can't set a property without an active room")?;
-                match parts.next().ok_or("Expected property name")? {
-                    "chance" => {
-                        prefab.chance = parts
-                            .next()
-                            .ok_or("Expected float after 'chance'")?
-                            .parse::<f32>()
-                            .map_err(|_| "Float after 'chance' is incorrectly formatted")?;
+                let prefab = match current_prefab.as_mut() {
+                    Some(current) => &mut current.prefab,
+                    None => {
+                        errors.push(
+                            line_number,
+                            column(line, offset),
+                            line.trim(),
+                            None,
+                            "Can't set a property without an active room",
+                        );
+                        continue;
                     }
-                    _ => return Err("Unknown property"),
+                };
+
+                parts.next();
+                if let Err((offset, text, message)) = parse_property(prefab, parts, line_end) {
+                    errors.push(
+                        line_number,
+                        column(line, offset),
+                        text,
+                        Some(prefab),
+                        message,
+                    );
                 }
             }
             body => {
-                let prefab = current_prefab
-                    .as_mut()
-                    .ok_or("Can't set tiles without an active room")?;
+                let prefab = match current_prefab.as_mut() {
+                    Some(current) => &mut current.prefab,
+                    None => {
+                        errors.push(
+                            line_number,
+                            column(line, offset),
+                            body,
+                            None,
+                            "Can't set tiles without an active room",
+                        );
+                        continue;
+                    }
+                };
+
+                prefab.rows.push((line_number, column(line, offset)));
 
-                let mut body_chars = body.chars();
+                let mut body_chars = body.char_indices();
                 let mut width = 0;
                 let mut x = 0;
-                while let Some(c) = body_chars.next() {
-                    let modifier = body_chars.next().ok_or("Expected tile modifier")?;
+                while let Some((tile_offset, c)) = body_chars.next() {
+                    let tile_offset = offset + tile_offset;
+                    let tile_text = &line[tile_offset..offset + body.len()];
+                    let modifier = match body_chars.next() {
+                        Some((_, modifier)) => modifier,
+                        None => {
+                            errors.push(
+                                line_number,
+                                column(line, tile_offset),
+                                tile_text,
+                                Some(prefab),
+                                "Expected tile modifier",
+                            );
+                            break;
+                        }
+                    };
+                    let tile_text = &tile_text[..c.len_utf8() + modifier.len_utf8()];
+
+                    let pos = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    width += 1;
+                    x += 1;
+
+                    if let Some(entry) = legend.get(&(c, modifier)) {
+                        if let Some(entity) = &entry.entity {
+                            prefab.entities.push((pos, entity.clone()));
+                        }
+                        prefab.tiles.push(GenTile::new(entry.tile.clone()));
+                        continue;
+                    }
+
                     prefab.tiles.push(match (c, modifier) {
                         ('#', '#') => GenTile::new(GenTileKind::Wall),
                         ('#', '>') => GenTile::new(GenTileKind::Wall)
@@ -665,9 +1517,7 @@ fn load_prefabs_from_path(path: &str) -> Result<Vec<RoomPrefab>, &'static str> {
                         ('o', 'o') => GenTile::new(GenTileKind::Window),
                         ('.', '.') => GenTile::new(GenTileKind::Floor),
                         ('E', '.') => {
-                            prefab
-                                .entities
-                                .push((Vec2::new(x as f32 + 0.5, y as f32 + 0.5), GenEntity::Evil));
+                            prefab.entities.push((pos, GenEntity::Evil));
                             GenTile::new(GenTileKind::Floor)
                         }
                         ('D', 'D') => GenTile::new(GenTileKind::Door),
@@ -679,16 +1529,31 @@ fn load_prefabs_from_path(path: &str) -> Result<Vec<RoomPrefab>, &'static str> {
                             .change_if(GenTileKind::Door, Direction::Up),
                         ('D', 'v') => GenTile::new(GenTileKind::Wall)
                             .change_if(GenTileKind::Door, Direction::Down),
-                        _ => return Err("Invalid tile character"),
+                        _ => {
+                            errors.push(
+                                line_number,
+                                column(line, tile_offset),
+                                tile_text,
+                                Some(prefab),
+                                "Invalid tile character",
+                            );
+                            // Keeps the size of the room right, so that there aren't more
+                            // errors about it.
+                            GenTile::new(GenTileKind::Floor)
+                        }
                     });
-                    width += 1;
-                    x += 1;
                 }
 
                 if prefab.n_rooms_width == 0 {
                     prefab.n_rooms_width = width / ROOM_WIDTH;
                 } else if prefab.n_rooms_width * ROOM_WIDTH != width {
-                    return Err("Widths don't match");
+                    errors.push(
+                        line_number,
+                        column(line, offset),
+                        body,
+                        Some(prefab),
+                        "Widths don't match",
+                    );
                 }
 
                 y += 1;
@@ -696,16 +1561,156 @@ fn load_prefabs_from_path(path: &str) -> Result<Vec<RoomPrefab>, &'static str> {
         }
     }
 
-    if let Some(mut prefab) = current_prefab.take() {
-        prefab.n_rooms_height = prefab
-            .tiles
-            .len()
-            .checked_div(prefab.n_rooms_width * ROOM_WIDTH * ROOM_HEIGHT)
-            .unwrap_or(0);
+    finish_prefab(current_prefab.take(), &mut prefabs, errors);
+
+    prefabs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(file_contents: &str) -> (Vec<RoomPrefab>, Vec<PrefabError>) {
+        let mut errors = PrefabErrors {
+            file: "test.txt",
+            errors: Vec::new(),
+        };
+        let prefabs = parse_prefabs(file_contents, &mut errors);
+        (prefabs, errors.errors)
+    }
+
+    /// The line, column, text, room and message of every error.
+    fn describe(errors: &[PrefabError]) -> Vec<(usize, usize, &str, Option<&str>, &str)> {
+        errors
+            .iter()
+            .map(|error| {
+                (
+                    error.line,
+                    error.column,
+                    error.text.as_str(),
+                    error.prefab.as_deref(),
+                    error.message,
+                )
+            })
+            .collect()
+    }
 
-        validate_room_prefab(&prefab)?;
-        prefabs.push(prefab);
+    #[test]
+    fn valid_room() {
+        let (prefabs, errors) = parse(
+            "-- Room\n\
+             - chance 2.5\n\
+             ########\n\
+             #<..E.#>\n\
+             #<....#>\n\
+             ########\n",
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(prefabs.len(), 1);
+        assert_eq!(prefabs[0].name, "Room");
+        assert_eq!(prefabs[0].entities.len(), 1);
     }
 
-    Ok(prefabs)
+    #[test]
+    fn invalid_tile_character() {
+        let (prefabs, errors) = parse(
+            "-- Room\n\
+             ########\n\
+             ..XX....\n\
+             ........\n\
+             ########\n",
+        );
+        assert!(prefabs.is_empty());
+        assert_eq!(
+            describe(&errors),
+            [(3, 3, "XX", Some("Room"), "Invalid tile character")]
+        );
+    }
+
+    #[test]
+    fn widths_dont_match() {
+        let (_, errors) = parse(
+            "-- Room\n\
+             ########\n\
+             ..........\n",
+        );
+        assert_eq!(
+            describe(&errors),
+            [(3, 1, "..........", Some("Room"), "Widths don't match")]
+        );
+    }
+
+    #[test]
+    fn property_before_room() {
+        let (_, errors) = parse("- chance 1.0\n");
+        assert_eq!(
+            describe(&errors),
+            [(
+                1,
+                1,
+                "- chance 1.0",
+                None,
+                "Can't set a property without an active room"
+            )]
+        );
+    }
+
+    #[test]
+    fn unknown_legend_entries() {
+        let (_, errors) = parse(
+            "== legend\n\
+             ab floor ghost\n\
+             cd lava\n",
+        );
+        assert_eq!(
+            describe(&errors),
+            [
+                (2, 10, "ghost", None, "Unknown entity type"),
+                (3, 4, "lava", None, "Unknown tile name"),
+            ]
+        );
+    }
+
+    #[test]
+    fn odd_length_row() {
+        let (_, errors) = parse(
+            "-- Room\n\
+             #######\n",
+        );
+        assert_eq!(
+            describe(&errors),
+            [(2, 7, "#", Some("Room"), "Expected tile modifier")]
+        );
+    }
+
+    #[test]
+    fn all_problems_are_reported() {
+        let (prefabs, errors) = parse(
+            "- chance 1.0\n\
+             -- Broken\n\
+             - size 3\n\
+             ########\n\
+             ..??....\n\
+             ......\n\
+             == legend\n\
+             xy lava\n",
+        );
+        assert!(prefabs.is_empty());
+        assert_eq!(
+            describe(&errors),
+            [
+                (
+                    1,
+                    1,
+                    "- chance 1.0",
+                    None,
+                    "Can't set a property without an active room"
+                ),
+                (3, 3, "size", Some("Broken"), "Unknown property"),
+                (5, 3, "??", Some("Broken"), "Invalid tile character"),
+                (6, 1, "......", Some("Broken"), "Widths don't match"),
+                (8, 4, "lava", None, "Unknown tile name"),
+            ]
+        );
+    }
 }
@@ -0,0 +1,200 @@
+use super::{GenEntity, GeneratedLevel, LevelGenerator};
+use crate::random::Random;
+use crate::world::{Tile, TileKind, TileMap};
+use crate::Vec2;
+
+/// The smallest a room can be, in tiles.
+const MIN_ROOM_SIZE: usize = 5;
+
+/// Splits the level in two over and over, puts a room in every part, and connects the parts
+/// that were split apart with corridors.
+pub struct BspGenerator {
+    width: usize,
+    height: usize,
+    /// Parts of the level aren't split if that would make them smaller than this.
+    min_area_size: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Area {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Area {
+    fn center(&self) -> (usize, usize) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+}
+
+/// Connects the centers of two rooms, going along x first and then along y.
+#[derive(Clone, Copy)]
+struct Corridor {
+    from: (usize, usize),
+    to: (usize, usize),
+}
+
+impl BspGenerator {
+    /// The size is in tiles.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            min_area_size: 16,
+        }
+    }
+
+    /// Splits the area, and returns one of the rooms in it, for connecting it to other areas.
+    fn split(
+        &self,
+        area: Area,
+        random: &mut Random,
+        rooms: &mut Vec<Area>,
+        corridors: &mut Vec<Corridor>,
+    ) -> usize {
+        let can_split_x = area.width >= self.min_area_size * 2;
+        let can_split_y = area.height >= self.min_area_size * 2;
+        let split_x = match (can_split_x, can_split_y) {
+            (false, false) => {
+                // Rooms keep a wall between them and the edge of the area.
+                let width = random_in(random, MIN_ROOM_SIZE, area.width - 2);
+                let height = random_in(random, MIN_ROOM_SIZE, area.height - 2);
+                rooms.push(Area {
+                    x: area.x + random_in(random, 1, area.width - width - 1),
+                    y: area.y + random_in(random, 1, area.height - height - 1),
+                    width,
+                    height,
+                });
+                return rooms.len() - 1;
+            }
+            (true, false) => true,
+            (false, true) => false,
+            (true, true) => area.width > area.height,
+        };
+
+        let (first, second) = if split_x {
+            let width = random_in(random, self.min_area_size, area.width - self.min_area_size);
+            (
+                Area { width, ..area },
+                Area {
+                    x: area.x + width,
+                    width: area.width - width,
+                    ..area
+                },
+            )
+        } else {
+            let height = random_in(random, self.min_area_size, area.height - self.min_area_size);
+            (
+                Area { height, ..area },
+                Area {
+                    y: area.y + height,
+                    height: area.height - height,
+                    ..area
+                },
+            )
+        };
+
+        let first = self.split(first, random, rooms, corridors);
+        let second = self.split(second, random, rooms, corridors);
+        corridors.push(Corridor {
+            from: rooms[first].center(),
+            to: rooms[second].center(),
+        });
+        if random.get_float() < 0.5 {
+            first
+        } else {
+            second
+        }
+    }
+}
+
+impl LevelGenerator for BspGenerator {
+    fn generate_level(&self, random: &mut Random, start: Vec2) -> GeneratedLevel {
+        let mut rooms = Vec::new();
+        let mut corridors = Vec::new();
+        let level = Area {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        };
+        self.split(level, random, &mut rooms, &mut corridors);
+
+        let mut floor = vec![false; self.width * self.height];
+        for room in rooms.iter() {
+            for y in room.y..room.y + room.height {
+                for x in room.x..room.x + room.width {
+                    floor[y * self.width + x] = true;
+                }
+            }
+        }
+        for corridor in corridors.iter() {
+            let ((from_x, from_y), (to_x, to_y)) = (corridor.from, corridor.to);
+            for x in from_x.min(to_x)..=from_x.max(to_x) {
+                floor[from_y * self.width + x] = true;
+            }
+            for y in from_y.min(to_y)..=from_y.max(to_y) {
+                floor[y * self.width + to_x] = true;
+            }
+        }
+
+        // The player starts in the middle of the room closest to where they should start.
+        let distance_to_start = |room: &Area| {
+            let (x, y) = room.center();
+            (Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - start).magnitude_squared()
+        };
+        let start_room = (0..rooms.len())
+            .min_by(|&a, &b| {
+                distance_to_start(&rooms[a])
+                    .partial_cmp(&distance_to_start(&rooms[b]))
+                    .unwrap()
+            })
+            .unwrap();
+        let (start_x, start_y) = rooms[start_room].center();
+
+        let mut entities = Vec::new();
+        for (i, room) in rooms.iter().enumerate() {
+            if i == start_room {
+                continue;
+            }
+
+            let n_evils = random.get_32() as usize % (room.width * room.height / 40 + 1);
+            for _ in 0..n_evils {
+                let pos = Vec2::new(
+                    room.x as f32 + 0.5 + random.get_float() * (room.width - 1) as f32,
+                    room.y as f32 + 0.5 + random.get_float() * (room.height - 1) as f32,
+                );
+                entities.push((pos, GenEntity::Evil));
+            }
+            if random.get_float() < 0.1 {
+                let (x, y) = room.center();
+                entities.push((Vec2::new(x as f32 + 0.5, y as f32 + 0.5), GenEntity::Rick));
+            }
+        }
+
+        let tiles = floor
+            .iter()
+            .map(|&floor| {
+                Tile::new(if floor {
+                    TileKind::Floor
+                } else {
+                    TileKind::Wall
+                })
+            })
+            .collect();
+
+        GeneratedLevel {
+            tiles: TileMap::new(tiles, self.width, self.height),
+            entities,
+            start: Vec2::new(start_x as f32 + 0.5, start_y as f32 + 0.5),
+            rooms: None,
+        }
+    }
+}
+
+/// A random number from `min` up to and including `max`.
+fn random_in(random: &mut Random, min: usize, max: usize) -> usize {
+    min + random.get_32() as usize % (max - min + 1)
+}
@@ -0,0 +1,183 @@
+use super::{GenEntity, GeneratedLevel, LevelGenerator};
+use crate::random::Random;
+use crate::world::{Tile, TileKind, TileMap};
+use crate::Vec2;
+
+/// How much of the level the biggest cave has to cover, levels with less floor are made again.
+const MIN_FLOOR_FRACTION: f32 = 0.25;
+/// How many levels to make before settling for the one with the biggest cave.
+const MAX_ATTEMPTS: usize = 10;
+
+/// Makes winding caves by filling the level with noise, and smoothing it out so that tiles
+/// become walls if most of the tiles around them are walls.
+pub struct CaveGenerator {
+    width: usize,
+    height: usize,
+    /// The chance of a tile starting out as a wall, more of them make narrower caves.
+    wall_chance: f32,
+    smoothing_steps: usize,
+}
+
+impl CaveGenerator {
+    /// The size is in tiles.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            wall_chance: 0.48,
+            smoothing_steps: 5,
+        }
+    }
+
+    fn n_walls_around(&self, walls: &[bool], x: usize, y: usize) -> usize {
+        let mut n_walls = 0;
+        for off_y in -1..=1 {
+            for off_x in -1..=1 {
+                let (x, y) = (x as isize + off_x, y as isize + off_y);
+                // Outside of the level counts as wall, so that the caves don't touch the edge.
+                if x < 0
+                    || y < 0
+                    || x as usize >= self.width
+                    || y as usize >= self.height
+                    || walls[y as usize * self.width + x as usize]
+                {
+                    n_walls += 1;
+                }
+            }
+        }
+        n_walls
+    }
+
+    /// Fills the level with noise and smooths it out, keeping the area around the start open.
+    fn make_walls(&self, random: &mut Random, start_x: usize, start_y: usize) -> Vec<bool> {
+        let mut walls: Vec<bool> = (0..self.width * self.height)
+            .map(|_| random.get_float() < self.wall_chance)
+            .collect();
+        let mut next = walls.clone();
+        for _ in 0..self.smoothing_steps {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    next[y * self.width + x] =
+                        !is_near(x, y, start_x, start_y) && self.n_walls_around(&walls, x, y) >= 5;
+                }
+            }
+            std::mem::swap(&mut walls, &mut next);
+        }
+        walls
+    }
+
+    /// Finds the biggest connected cave, and returns which tiles are in it, and how many.
+    fn biggest_cave(&self, walls: &[bool]) -> (Vec<bool>, usize) {
+        const NO_CAVE: usize = usize::MAX;
+        let mut caves = vec![NO_CAVE; walls.len()];
+        let mut biggest = (NO_CAVE, 0);
+        let mut to_visit = Vec::new();
+        for start in 0..walls.len() {
+            if walls[start] || caves[start] != NO_CAVE {
+                continue;
+            }
+
+            let cave = start;
+            let mut size = 0;
+            caves[start] = cave;
+            to_visit.push(start);
+            while let Some(index) = to_visit.pop() {
+                size += 1;
+                let (x, y) = (index % self.width, index / self.width);
+                // The neighbours off the edge wrap around, but they aren't used.
+                let neighbours = [
+                    (x > 0, index.wrapping_sub(1)),
+                    (x + 1 < self.width, index + 1),
+                    (y > 0, index.wrapping_sub(self.width)),
+                    (y + 1 < self.height, index + self.width),
+                ];
+                for &(exists, neighbour) in neighbours.iter() {
+                    if exists && !walls[neighbour] && caves[neighbour] == NO_CAVE {
+                        caves[neighbour] = cave;
+                        to_visit.push(neighbour);
+                    }
+                }
+            }
+
+            if size > biggest.1 {
+                biggest = (cave, size);
+            }
+        }
+
+        let in_cave = caves.iter().map(|&cave| cave == biggest.0).collect();
+        (in_cave, biggest.1)
+    }
+}
+
+fn is_near(x: usize, y: usize, start_x: usize, start_y: usize) -> bool {
+    (x as isize - start_x as isize).abs() <= 2 && (y as isize - start_y as isize).abs() <= 2
+}
+
+/// Only the biggest cave is kept, and the others are filled in. If the player wouldn't start
+/// in it, they start on the floor of it that is closest to where they would have.
+impl LevelGenerator for CaveGenerator {
+    fn generate_level(&self, random: &mut Random, start: Vec2) -> GeneratedLevel {
+        let (start_x, start_y) = (start.x as usize, start.y as usize);
+        let min_floor = (MIN_FLOOR_FRACTION * (self.width * self.height) as f32) as usize;
+
+        let mut in_cave = Vec::new();
+        let mut n_floor = 0;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let walls = self.make_walls(random, start_x, start_y);
+            let (cave, size) = self.biggest_cave(&walls);
+            if size > n_floor {
+                in_cave = cave;
+                n_floor = size;
+            }
+            if n_floor >= min_floor {
+                break;
+            }
+            if attempt == MAX_ATTEMPTS {
+                eprintln!(
+                    "The biggest cave only has {} floor tiles, {} were wanted",
+                    n_floor, min_floor
+                );
+            }
+        }
+
+        let mut start = start;
+        if !in_cave[start_y * self.width + start_x] {
+            let distance = |index: usize| {
+                let (x, y) = (index % self.width, index / self.width);
+                (x as isize - start_x as isize).pow(2) + (y as isize - start_y as isize).pow(2)
+            };
+            if let Some(index) = (0..in_cave.len())
+                .filter(|&index| in_cave[index])
+                .min_by_key(|&index| distance(index))
+            {
+                start = Vec2::new(
+                    (index % self.width) as f32 + 0.5,
+                    (index / self.width) as f32 + 0.5,
+                );
+            }
+        }
+        let (start_x, start_y) = (start.x as usize, start.y as usize);
+
+        let mut entities = Vec::new();
+        let mut tiles = Vec::with_capacity(in_cave.len());
+        for (index, &in_cave) in in_cave.iter().enumerate() {
+            if !in_cave {
+                tiles.push(Tile::new(TileKind::Wall));
+                continue;
+            }
+
+            tiles.push(Tile::new(TileKind::Floor));
+            let (x, y) = (index % self.width, index / self.width);
+            if !is_near(x, y, start_x, start_y) && random.get_float() < 0.003 {
+                entities.push((Vec2::new(x as f32 + 0.5, y as f32 + 0.5), GenEntity::Evil));
+            }
+        }
+
+        GeneratedLevel {
+            tiles: TileMap::new(tiles, self.width, self.height),
+            entities,
+            start,
+            rooms: None,
+        }
+    }
+}
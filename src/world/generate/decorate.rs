@@ -0,0 +1,322 @@
+use super::{
+    column, spawn_evil, spawn_rick, value, words, GenEntity, ParseError, PrefabError, PrefabErrors,
+    RoomGraph, RoomTag, ROOM_HEIGHT, ROOM_WIDTH,
+};
+use crate::random::Random;
+use crate::texture::{Animation, Texture};
+use crate::world::{StaticSprite, TileKind, World};
+use crate::Vec2;
+
+/// What a decoration puts in the world.
+enum Spawn {
+    Sprite(Texture),
+    Entity(GenEntity),
+}
+
+/// A rule for scattering something over the floor.
+struct Decoration {
+    name: String,
+    line: usize,
+    spawn: Option<Spawn>,
+    /// The average number of them on a floor tile.
+    density: f32,
+    /// How many random numbers are multiplied together for the number of them on a tile.
+    /// Higher makes most tiles empty and a few of them crowded, with the same average.
+    clustering: u32,
+    min_size: f32,
+    max_size: f32,
+    /// How high up sprites are drawn.
+    y_pos: f32,
+    /// The tags of the rooms it's placed in, it's placed everywhere if there are none.
+    tags: Vec<RoomTag>,
+}
+
+impl Decoration {
+    fn new(name: String, line: usize) -> Self {
+        Self {
+            name,
+            line,
+            spawn: None,
+            density: 0.0,
+            clustering: 0,
+            min_size: 1.0,
+            max_size: 1.0,
+            y_pos: 0.0,
+            tags: Vec::new(),
+        }
+    }
+
+    /// How many to place on a tile. The fraction of the average is rounded up by chance,
+    /// so that densities below one still place some.
+    fn count(&self, random: &mut Random) -> u32 {
+        let mut count = self.density;
+        for _ in 0..self.clustering {
+            count *= random.get_float() * 2.0;
+        }
+        (count + random.get_float()) as u32
+    }
+}
+
+/// The things that are scattered over the floor once a level has been made.
+pub struct Decorations {
+    decorations: Vec<Decoration>,
+}
+
+impl Decorations {
+    /// Loads the decorations. Like the room prefabs, every decoration starts with a `-- <name>`
+    /// line, followed by `- <property> <values>` lines. The properties are `sprite <texture>`
+    /// or `entity <entity>`, `density`, `clustering`, `size <min> <max>`, `y_pos` and `tags`.
+    pub fn load(path: &str) -> Result<Self, Vec<PrefabError>> {
+        let mut errors = PrefabErrors {
+            file: path,
+            errors: Vec::new(),
+        };
+
+        let file_contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => {
+                errors.push(0, 0, "", None, "Couldn't load file");
+                return Err(errors.errors);
+            }
+        };
+
+        let decorations = parse_decorations(&file_contents, &mut errors);
+        if errors.errors.is_empty() {
+            Ok(Self { decorations })
+        } else {
+            Err(errors.errors)
+        }
+    }
+
+    /// Places the decorations on every floor tile. The tags of a tile are the ones of the
+    /// room it's in, so tagged decorations are only placed in levels with rooms.
+    pub(super) fn apply(&self, world: &mut World, random: &mut Random, rooms: Option<&RoomGraph>) {
+        let (width, height) = (world.tiles.width(), world.tiles.height());
+        let n_rooms_width = (width + ROOM_WIDTH - 1) / ROOM_WIDTH;
+        let n_rooms_height = (height + ROOM_HEIGHT - 1) / ROOM_HEIGHT;
+        let mut room_tags: Vec<&[RoomTag]> = vec![&[]; n_rooms_width * n_rooms_height];
+        for room in rooms.iter().flat_map(|rooms| rooms.rooms.iter()) {
+            for y in room.y as usize..room.y as usize + room.height {
+                for x in room.x as usize..room.x as usize + room.width {
+                    room_tags[y * n_rooms_width + x] = &room.tags;
+                }
+            }
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                if !matches!(
+                    world.tiles.get(x as isize, y as isize).unwrap().kind(),
+                    TileKind::Floor
+                ) {
+                    continue;
+                }
+
+                let tags = room_tags[(y / ROOM_HEIGHT) * n_rooms_width + x / ROOM_WIDTH];
+                for decoration in self.decorations.iter() {
+                    if !decoration.tags.is_empty()
+                        && !decoration.tags.iter().any(|tag| tags.contains(tag))
+                    {
+                        continue;
+                    }
+
+                    for _ in 0..decoration.count(random) {
+                        let pos =
+                            Vec2::new(x as f32 + random.get_float(), y as f32 + random.get_float());
+                        match decoration.spawn {
+                            Some(Spawn::Sprite(texture)) => {
+                                let size = decoration.min_size
+                                    + random.get_float()
+                                        * (decoration.max_size - decoration.min_size);
+                                world.tiles.insert_static_sprite(StaticSprite {
+                                    animation: Animation::new_loop_with_time(texture, 0.0),
+                                    pos,
+                                    size,
+                                    y_pos: decoration.y_pos,
+                                });
+                            }
+                            Some(Spawn::Entity(GenEntity::Evil)) => spawn_evil(world, pos),
+                            Some(Spawn::Entity(GenEntity::Rick)) => spawn_rick(world, random, pos),
+                            None => (),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_decorations(file_contents: &str, errors: &mut PrefabErrors<'_>) -> Vec<Decoration> {
+    /// Adds the decoration if it's valid, problems are reported at the line it started on.
+    fn finish_decoration(
+        decoration: Option<(usize, Decoration)>,
+        decorations: &mut Vec<Decoration>,
+        errors: &mut PrefabErrors<'_>,
+    ) {
+        if let Some((n_errors, decoration)) = decoration {
+            if errors.errors.len() > n_errors {
+                return;
+            }
+
+            if decoration.spawn.is_none() {
+                errors.push(
+                    decoration.line,
+                    1,
+                    &decoration.name,
+                    None,
+                    "Decoration has no 'sprite' or 'entity'",
+                );
+            } else if decoration.min_size > decoration.max_size {
+                errors.push(
+                    decoration.line,
+                    1,
+                    &decoration.name,
+                    None,
+                    "The smallest 'size' is larger than the largest",
+                );
+            } else {
+                decorations.push(decoration);
+            }
+        }
+    }
+
+    /// Parses a `- <property> <values>` line, and on failure returns the word that's wrong.
+    fn parse_property<'a>(
+        decoration: &mut Decoration,
+        mut parts: impl Iterator<Item = (usize, &'a str)>,
+        line_end: usize,
+    ) -> Result<(), ParseError<'a>> {
+        let (offset, name) = parts
+            .next()
+            .ok_or((line_end, "", "Expected property name"))?;
+        match name {
+            "sprite" => {
+                let (offset, name) = parts
+                    .next()
+                    .ok_or((line_end, "", "Expected texture name"))?;
+                let texture = Texture::from_name(name).ok_or((offset, name, "Unknown texture"))?;
+                decoration.spawn = Some(Spawn::Sprite(texture));
+            }
+            "entity" => {
+                let (offset, name) = parts.next().ok_or((line_end, "", "Expected entity type"))?;
+                let entity =
+                    GenEntity::from_name(name).ok_or((offset, name, "Unknown entity type"))?;
+                decoration.spawn = Some(Spawn::Entity(entity));
+            }
+            "density" => {
+                decoration.density = value(
+                    parts.next(),
+                    line_end,
+                    "Expected float after 'density'",
+                    "Float after 'density' is incorrectly formatted",
+                )?;
+            }
+            "clustering" => {
+                decoration.clustering = value(
+                    parts.next(),
+                    line_end,
+                    "Expected integer after 'clustering'",
+                    "Integer after 'clustering' is incorrectly formatted",
+                )?;
+            }
+            "size" => {
+                decoration.min_size = value(
+                    parts.next(),
+                    line_end,
+                    "Expected smallest and largest size after 'size'",
+                    "Float after 'size' is incorrectly formatted",
+                )?;
+                decoration.max_size = value(
+                    parts.next(),
+                    line_end,
+                    "Expected largest size after the smallest",
+                    "Float after 'size' is incorrectly formatted",
+                )?;
+            }
+            "y_pos" => {
+                decoration.y_pos = value(
+                    parts.next(),
+                    line_end,
+                    "Expected float after 'y_pos'",
+                    "Float after 'y_pos' is incorrectly formatted",
+                )?;
+            }
+            "tags" => {
+                for (offset, tag) in parts {
+                    decoration.tags.push(RoomTag::from_name(tag).ok_or((
+                        offset,
+                        tag,
+                        "Unknown room tag",
+                    ))?);
+                }
+            }
+            _ => return Err((offset, name, "Unknown property")),
+        }
+        Ok(())
+    }
+
+    let mut decorations = Vec::new();
+    let mut current: Option<(usize, Decoration)> = None;
+
+    for (line_index, line) in file_contents.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line_end = line.trim_end().len();
+        let mut parts = words(line);
+        let (offset, first) = match parts.next() {
+            Some(first) => first,
+            None => continue,
+        };
+
+        match first {
+            "--" => {
+                finish_decoration(current.take(), &mut decorations, errors);
+
+                let name = line.trim()[2..].trim();
+                if name.is_empty() {
+                    errors.push(
+                        line_number,
+                        column(line, line_end),
+                        "",
+                        None,
+                        "Expected name of decoration",
+                    );
+                }
+                current = Some((
+                    errors.errors.len(),
+                    Decoration::new(name.to_string(), line_number),
+                ));
+            }
+            "-" => {
+                let decoration = match current.as_mut() {
+                    Some((_, decoration)) => decoration,
+                    None => {
+                        errors.push(
+                            line_number,
+                            column(line, offset),
+                            line.trim(),
+                            None,
+                            "Can't set a property without an active decoration",
+                        );
+                        continue;
+                    }
+                };
+
+                if let Err((offset, text, message)) = parse_property(decoration, parts, line_end) {
+                    errors.push(line_number, column(line, offset), text, None, message);
+                }
+            }
+            _ => {
+                errors.push(
+                    line_number,
+                    column(line, offset),
+                    first,
+                    None,
+                    "Expected '--' or '-'",
+                );
+            }
+        }
+    }
+    finish_decoration(current.take(), &mut decorations, errors);
+
+    decorations
+}
@@ -0,0 +1,429 @@
+use super::{Direction, GrowState, RoomTag, Rooms, WorldGenerator, ROOM_HEIGHT, ROOM_WIDTH};
+
+/// Numbers about how the placed rooms are connected to each other.
+pub struct DungeonStats {
+    pub rooms: usize,
+    /// Rooms that can't be reached from the start room, which is none once the ones that
+    /// couldn't be connected are removed.
+    pub unreachable: usize,
+    /// Rooms that couldn't be connected to the others, and were filled with rock.
+    pub removed: usize,
+    pub connections: usize,
+    /// Connections that were made after growing the dungeon, so that more rooms can be reached.
+    pub added_connections: usize,
+    /// How many connections there are on top of the ones needed to reach every room.
+    pub loops: usize,
+    /// Rooms with only one way in or out.
+    pub dead_ends: usize,
+    /// The most connections that have to be walked through to get from the start room to
+    /// any other room.
+    pub longest_path: usize,
+}
+
+impl std::fmt::Display for DungeonStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} rooms ({} unreachable, {} removed), {} connections ({} added), {} loops, {} dead ends, longest path {}",
+            self.rooms,
+            self.unreachable,
+            self.removed,
+            self.connections,
+            self.added_connections,
+            self.loops,
+            self.dead_ends,
+            self.longest_path,
+        )
+    }
+}
+
+impl WorldGenerator {
+    /// Returns true if something can walk from the room at (x, y) into the one next to it,
+    /// with the openings of both rooms applied.
+    fn has_opening(&self, rooms: &Rooms, x: isize, y: isize, direction: Direction) -> bool {
+        let (off_x, off_y) = direction.to_vec();
+        let (from, to) = match (rooms.get(x, y), rooms.get(x + off_x, y + off_y)) {
+            (Some(Some(from)), Some(Some(to))) => (from, to),
+            _ => return false,
+        };
+
+        let n_tiles = if off_x != 0 { ROOM_HEIGHT } else { ROOM_WIDTH };
+        (0..n_tiles).any(|i| {
+            let (from_x, from_y, to_x, to_y) = match direction {
+                Direction::Left => (0, i, ROOM_WIDTH - 1, i),
+                Direction::Right => (ROOM_WIDTH - 1, i, 0, i),
+                Direction::Up => (i, 0, i, ROOM_HEIGHT - 1),
+                Direction::Down => (i, ROOM_HEIGHT - 1, i, 0),
+            };
+            self.tile_kind_at(from, from_x, from_y).is_passable()
+                && self.tile_kind_at(to, to_x, to_y).is_passable()
+        })
+    }
+
+    /// Finds which placed rooms can be walked between, for every placed room.
+    pub(super) fn room_connections(&self, rooms: &Rooms, n_placed: usize) -> Vec<Vec<usize>> {
+        let mut connections = vec![Vec::new(); n_placed];
+        for y in 0..rooms.height as isize {
+            for x in 0..rooms.width as isize {
+                for &direction in &[Direction::Right, Direction::Down] {
+                    let (off_x, off_y) = direction.to_vec();
+                    if let (Some(Some(from)), Some(Some(to))) =
+                        (rooms.get(x, y), rooms.get(x + off_x, y + off_y))
+                    {
+                        if from.instance != to.instance && self.has_opening(rooms, x, y, direction)
+                        {
+                            add_connection(&mut connections, from.instance, to.instance);
+                        }
+                    }
+                }
+            }
+        }
+        connections
+    }
+
+    /// Opens the sides of rooms that can't be reached from the start room towards rooms that
+    /// can, until every room can be reached. Rooms without openings on the right sides stay
+    /// unreachable, see `remove_unreachable_rooms`. Returns how many connections were made.
+    pub(super) fn connect_all_rooms(
+        &self,
+        rooms: &mut Rooms,
+        connections: &mut [Vec<usize>],
+        start: usize,
+    ) -> usize {
+        let mut reached = vec![false; connections.len()];
+        reached[start] = true;
+        let mut to_visit = vec![start];
+        let mut n_added = 0;
+
+        loop {
+            while let Some(room) = to_visit.pop() {
+                for &other in connections[room].iter() {
+                    if !reached[other] {
+                        reached[other] = true;
+                        to_visit.push(other);
+                    }
+                }
+            }
+
+            if reached.iter().all(|&reached| reached) {
+                break;
+            }
+
+            let mut made_connection = false;
+            for y in 0..rooms.height as isize {
+                for x in 0..rooms.width as isize {
+                    for &direction in &[
+                        Direction::Left,
+                        Direction::Right,
+                        Direction::Up,
+                        Direction::Down,
+                    ] {
+                        let (off_x, off_y) = direction.to_vec();
+                        let (from, to) = match (rooms.get(x, y), rooms.get(x + off_x, y + off_y)) {
+                            (Some(Some(from)), Some(Some(to))) => (from.instance, to.instance),
+                            _ => continue,
+                        };
+                        if !reached[from] || reached[to] {
+                            continue;
+                        }
+
+                        let was_open = (
+                            get_dir(rooms, x, y, direction),
+                            get_dir(rooms, x + off_x, y + off_y, direction.inverted()),
+                        );
+                        set_dir(rooms, x, y, direction, true);
+                        set_dir(rooms, x + off_x, y + off_y, direction.inverted(), true);
+
+                        if self.has_opening(rooms, x, y, direction) {
+                            add_connection(connections, from, to);
+                            reached[to] = true;
+                            to_visit.push(to);
+                            n_added += 1;
+                            made_connection = true;
+                        } else {
+                            set_dir(rooms, x, y, direction, was_open.0);
+                            set_dir(
+                                rooms,
+                                x + off_x,
+                                y + off_y,
+                                direction.inverted(),
+                                was_open.1,
+                            );
+                        }
+                    }
+                }
+            }
+
+            if !made_connection {
+                break;
+            }
+        }
+
+        n_added
+    }
+
+    /// Fills the rooms that can't be reached from the start room with rock, and takes out the
+    /// entities in them, so that everything left in the level can be walked to. The sides of
+    /// the other rooms are closed towards the rock. Returns how many placed rooms were removed.
+    pub(super) fn remove_unreachable_rooms(
+        &self,
+        rooms: &mut Rooms,
+        state: &mut GrowState,
+        connections: &mut Vec<Vec<usize>>,
+        start: usize,
+    ) -> usize {
+        let reached: Vec<bool> = distances(connections, start)
+            .iter()
+            .map(|distance| distance.is_some())
+            .collect();
+        let n_removed = reached.iter().filter(|&&reached| !reached).count();
+        if n_removed == 0 {
+            return 0;
+        }
+
+        // The rooms that are left are numbered again, in the same order.
+        let mut new_instances = Vec::with_capacity(reached.len());
+        let mut n_kept = 0;
+        for &reached in reached.iter() {
+            new_instances.push(if reached { Some(n_kept) } else { None });
+            n_kept += reached as usize;
+        }
+
+        for room in rooms.rooms.iter_mut() {
+            if let Some(instance) = room.as_ref().map(|room| new_instances[room.instance]) {
+                match instance {
+                    Some(instance) => room.as_mut().unwrap().instance = instance,
+                    None => *room = None,
+                }
+            }
+        }
+
+        for y in 0..rooms.height as isize {
+            for x in 0..rooms.width as isize {
+                for &direction in &[
+                    Direction::Left,
+                    Direction::Right,
+                    Direction::Up,
+                    Direction::Down,
+                ] {
+                    let (off_x, off_y) = direction.to_vec();
+                    if !matches!(rooms.get(x + off_x, y + off_y), Some(Some(_))) {
+                        set_dir(rooms, x, y, direction, false);
+                    }
+                }
+            }
+        }
+
+        state.entities.retain(|(pos, _)| {
+            let (x, y) = (
+                (pos.x / ROOM_WIDTH as f32).floor() as isize,
+                (pos.y / ROOM_HEIGHT as f32).floor() as isize,
+            );
+            matches!(rooms.get(x, y), Some(Some(_)))
+        });
+
+        let mut instances = reached.iter();
+        state.placed.retain(|_| *instances.next().unwrap());
+
+        let old_connections = std::mem::take(connections);
+        for (room_connections, &reached) in old_connections.into_iter().zip(reached.iter()) {
+            if reached {
+                // Rooms that can be reached are only connected to other rooms that can be.
+                connections.push(
+                    room_connections
+                        .into_iter()
+                        .map(|other| new_instances[other].unwrap())
+                        .collect(),
+                );
+            }
+        }
+
+        n_removed
+    }
+}
+
+/// A prefab that has been placed in the world.
+pub struct PlacedRoom {
+    pub name: String,
+    pub tags: Vec<RoomTag>,
+    /// The position of the top left corner, and the size, in rooms.
+    pub x: isize,
+    pub y: isize,
+    pub width: usize,
+    pub height: usize,
+    /// How many rooms away from the start room it was when it was placed.
+    pub depth: usize,
+    /// The fewest connections that have to be walked through to get here from the start room,
+    /// None if it can't be reached.
+    pub distance: Option<usize>,
+    /// The rooms that can be walked to from this one.
+    pub connections: Vec<usize>,
+}
+
+/// The prefabs that were placed in a world, and how they connect to each other.
+pub struct RoomGraph {
+    pub rooms: Vec<PlacedRoom>,
+    /// The room the player starts in.
+    pub start: usize,
+    pub stats: DungeonStats,
+}
+
+impl RoomGraph {
+    /// Fills in the connections and the distances from the start room.
+    pub(super) fn new(
+        mut rooms: Vec<PlacedRoom>,
+        connections: Vec<Vec<usize>>,
+        start: usize,
+        added_connections: usize,
+        removed: usize,
+    ) -> Self {
+        let distances = distances(&connections, start);
+        let stats = dungeon_stats(&connections, &distances, added_connections, removed);
+        for ((room, connections), distance) in rooms.iter_mut().zip(connections).zip(distances) {
+            room.connections = connections;
+            room.distance = distance;
+        }
+
+        Self {
+            rooms,
+            start,
+            stats,
+        }
+    }
+
+    /// The room that is the most connections away from the start room.
+    pub fn farthest_room(&self) -> usize {
+        (0..self.rooms.len())
+            .max_by_key(|&room| self.rooms[room].distance)
+            .unwrap_or(self.start)
+    }
+
+    /// The graph in the Graphviz DOT format. Every room is placed where it is in the world,
+    /// which `neato -n` keeps.
+    pub fn to_dot(&self) -> String {
+        use std::fmt::Write;
+
+        let mut dot = String::new();
+        writeln!(dot, "graph rooms {{").unwrap();
+        writeln!(dot, "    node [shape=box, style=filled, fontsize=8];").unwrap();
+        for (i, room) in self.rooms.iter().enumerate() {
+            let color = if i == self.start {
+                "palegreen"
+            } else if room.distance.is_none() {
+                "gray"
+            } else if room.tags.contains(&RoomTag::Exit) {
+                "tomato"
+            } else if room.tags.contains(&RoomTag::Treasure) {
+                "gold"
+            } else {
+                "white"
+            };
+            let distance = match room.distance {
+                Some(distance) => distance.to_string(),
+                None => "unreachable".to_string(),
+            };
+            writeln!(
+                dot,
+                "    {} [label=\"{}\\n({}, {}) {}x{}\\ndepth {} distance {}\", fillcolor={}, pos=\"{},{}\"];",
+                i,
+                room.name.replace('"', "\\\""),
+                room.x,
+                room.y,
+                room.width,
+                room.height,
+                room.depth,
+                distance,
+                color,
+                room.x * 40,
+                -room.y * 40,
+            )
+            .unwrap();
+        }
+        for (i, room) in self.rooms.iter().enumerate() {
+            for &other in room.connections.iter().filter(|&&other| other > i) {
+                writeln!(dot, "    {} -- {};", i, other).unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    pub fn save_dot(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.to_dot())
+    }
+}
+
+/// The number of connections between `start` and every room, or None for the rooms that
+/// can't be reached.
+fn distances(connections: &[Vec<usize>], start: usize) -> Vec<Option<usize>> {
+    let mut distances = vec![None; connections.len()];
+    distances[start] = Some(0);
+    let mut to_visit = std::collections::VecDeque::new();
+    to_visit.push_back(start);
+    while let Some(room) = to_visit.pop_front() {
+        let distance = distances[room].unwrap();
+        for &other in connections[room].iter() {
+            if distances[other].is_none() {
+                distances[other] = Some(distance + 1);
+                to_visit.push_back(other);
+            }
+        }
+    }
+    distances
+}
+
+fn dungeon_stats(
+    connections: &[Vec<usize>],
+    distances: &[Option<usize>],
+    added_connections: usize,
+    removed: usize,
+) -> DungeonStats {
+    let reachable = distances
+        .iter()
+        .filter(|distance| distance.is_some())
+        .count();
+    // Every connection is in the lists of both of the rooms.
+    let reachable_connections = connections
+        .iter()
+        .zip(distances.iter())
+        .filter(|(_, distance)| distance.is_some())
+        .map(|(connections, _)| connections.len())
+        .sum::<usize>()
+        / 2;
+
+    DungeonStats {
+        rooms: connections.len(),
+        unreachable: connections.len() - reachable,
+        removed,
+        connections: connections
+            .iter()
+            .map(|connections| connections.len())
+            .sum::<usize>()
+            / 2,
+        added_connections,
+        loops: (reachable_connections + 1).saturating_sub(reachable),
+        dead_ends: connections
+            .iter()
+            .zip(distances.iter())
+            .filter(|(connections, distance)| distance.is_some() && connections.len() == 1)
+            .count(),
+        longest_path: distances.iter().flatten().copied().max().unwrap_or(0),
+    }
+}
+
+fn add_connection(connections: &mut [Vec<usize>], from: usize, to: usize) {
+    if !connections[from].contains(&to) {
+        connections[from].push(to);
+        connections[to].push(from);
+    }
+}
+
+fn get_dir(rooms: &Rooms, x: isize, y: isize, direction: Direction) -> bool {
+    matches!(rooms.get(x, y), Some(Some(room)) if room.get_dir(direction))
+}
+
+fn set_dir(rooms: &mut Rooms, x: isize, y: isize, direction: Direction, open: bool) {
+    if let Some(Some(room)) = rooms.get_mut(x, y) {
+        *room.get_dir_mut(direction) = open;
+    }
+}
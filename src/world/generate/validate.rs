@@ -0,0 +1,257 @@
+use super::{
+    Direction, GenTile, GenTileKind, PrefabError, RoomPrefab, RoomTag, WorldGenerator, ROOM_HEIGHT,
+    ROOM_WIDTH,
+};
+use crate::world::TileKind;
+
+/// How many pixels wide a tile is in the prefab images.
+const IMAGE_TILE_SIZE: u32 = 8;
+
+impl WorldGenerator {
+    /// Looks for problems in the prefabs that the loader doesn't catch, like openings that
+    /// don't face out of the room, floor that can't be reached, and rooms that can't be placed.
+    pub fn validate_prefabs(&self) -> Vec<PrefabError> {
+        let mut errors = Vec::new();
+        // The variants are made from the prefabs, so they only have the problems the
+        // prefabs already have.
+        for prefab in self
+            .prefabs
+            .iter()
+            .filter(|prefab| prefab.variant_of.is_none())
+        {
+            self.validate_prefab(prefab, &mut errors);
+        }
+        errors
+    }
+
+    fn validate_prefab(&self, prefab: &RoomPrefab, errors: &mut Vec<PrefabError>) {
+        let width = prefab.n_rooms_width * ROOM_WIDTH;
+        let height = prefab.n_rooms_height * ROOM_HEIGHT;
+
+        let mut error_at = |line: usize, column: usize, text: String, message: &'static str| {
+            errors.push(PrefabError {
+                file: self.prefabs_path.clone(),
+                line,
+                column,
+                text,
+                prefab: Some(prefab.name.clone()),
+                message,
+            });
+        };
+        let tile_location = |x: usize, y: usize| {
+            let (line, column) = prefab.rows[y];
+            (line, column + x * 2)
+        };
+
+        // Openings only open if the room is connected on that side, and rooms are only
+        // connected on the outside of the prefab.
+        for y in 0..height {
+            for x in 0..width {
+                let tile = &prefab.tiles[y * width + x];
+                if let Some((_, direction)) = &tile.change_if {
+                    let on_side = match direction {
+                        Direction::Left => x == 0,
+                        Direction::Right => x == width - 1,
+                        Direction::Up => y == 0,
+                        Direction::Down => y == height - 1,
+                    };
+                    if !on_side {
+                        let (line, column) = tile_location(x, y);
+                        error_at(
+                            line,
+                            column,
+                            tile_code(tile),
+                            "Opening isn't on the side of the room it opens towards",
+                        );
+                    }
+                }
+            }
+        }
+
+        // Every part of the floor should be reachable from the rest, when all the openings
+        // are open.
+        let mut area = vec![usize::MAX; prefab.tiles.len()];
+        let mut area_sizes = Vec::new();
+        let mut area_starts = Vec::new();
+        let mut stack = Vec::new();
+        for start in 0..prefab.tiles.len() {
+            if area[start] != usize::MAX || !is_passable(&prefab.tiles[start]) {
+                continue;
+            }
+
+            let area_id = area_sizes.len();
+            let mut size = 0;
+            area[start] = area_id;
+            stack.push(start);
+            while let Some(index) = stack.pop() {
+                size += 1;
+                let (x, y) = (index % width, index / width);
+                // The neighbours off the edge wrap around, but they aren't used.
+                let neighbours = [
+                    (x > 0, index.wrapping_sub(1)),
+                    (x + 1 < width, index + 1),
+                    (y > 0, index.wrapping_sub(width)),
+                    (y + 1 < height, index + width),
+                ];
+                for &(exists, neighbour) in neighbours.iter() {
+                    if exists
+                        && area[neighbour] == usize::MAX
+                        && is_passable(&prefab.tiles[neighbour])
+                    {
+                        area[neighbour] = area_id;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+            area_sizes.push(size);
+            area_starts.push(start);
+        }
+
+        if let Some(largest) = (0..area_sizes.len()).max_by_key(|&area| area_sizes[area]) {
+            for (area, &start) in area_starts.iter().enumerate() {
+                if area != largest {
+                    let (line, column) = tile_location(start % width, start / width);
+                    error_at(
+                        line,
+                        column,
+                        tile_code(&prefab.tiles[start]),
+                        "Floor isn't connected to the rest of the room",
+                    );
+                }
+            }
+        }
+
+        let header = |message| (prefab.line, 1, String::new(), message);
+        let mut placement_errors = Vec::new();
+        if prefab.n_rooms_width > self.n_rooms_width || prefab.n_rooms_height > self.n_rooms_height
+        {
+            placement_errors.push(header("Room is bigger than the world"));
+        }
+        if !prefab.has_tag(RoomTag::Start) {
+            if prefab.chance <= 0.0 {
+                placement_errors.push(header("Room has no chance of being picked"));
+            }
+            if prefab.max_count == 0 {
+                placement_errors.push(header("Room has a 'max_count' of zero"));
+            }
+        }
+        let enterable = (0..height).any(|y| {
+            (0..width).any(|x| {
+                (x == 0 || y == 0 || x == width - 1 || y == height - 1)
+                    && is_passable(&prefab.tiles[y * width + x])
+            })
+        });
+        if !enterable {
+            placement_errors.push(header("Room has no openings, so it can't be entered"));
+        }
+        for (line, column, text, message) in placement_errors {
+            error_at(line, column, text, message);
+        }
+    }
+
+    /// Saves a picture of every prefab into the directory, in the colors of `TileMap::to_image`.
+    /// Openings are drawn with what they open into in the middle, and entities as red dots.
+    pub fn save_prefab_images(&self, dir: &str) -> Result<(), &'static str> {
+        use image::{ImageBuffer, Pixel, Rgba};
+
+        std::fs::create_dir_all(dir).map_err(|_| "Couldn't create directory")?;
+
+        for (i, prefab) in self.prefabs.iter().enumerate() {
+            let width = prefab.n_rooms_width * ROOM_WIDTH;
+            let height = prefab.n_rooms_height * ROOM_HEIGHT;
+            let mut image = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(
+                width as u32 * IMAGE_TILE_SIZE,
+                height as u32 * IMAGE_TILE_SIZE,
+            );
+
+            for y in 0..height {
+                for x in 0..width {
+                    let tile = &prefab.tiles[y * width + x];
+                    let [r, g, b] = tile_kind(&tile.kind).map_color();
+                    let inner = tile
+                        .change_if
+                        .as_ref()
+                        .map(|(kind, _)| tile_kind(kind).map_color());
+
+                    for pixel_y in 0..IMAGE_TILE_SIZE {
+                        for pixel_x in 0..IMAGE_TILE_SIZE {
+                            let is_inner = (2..IMAGE_TILE_SIZE - 2).contains(&pixel_x)
+                                && (2..IMAGE_TILE_SIZE - 2).contains(&pixel_y);
+                            let [r, g, b] = match inner {
+                                Some(inner) if is_inner => inner,
+                                _ => [r, g, b],
+                            };
+                            image.put_pixel(
+                                x as u32 * IMAGE_TILE_SIZE + pixel_x,
+                                y as u32 * IMAGE_TILE_SIZE + pixel_y,
+                                Pixel::from_channels(r, g, b, 255),
+                            );
+                        }
+                    }
+                }
+            }
+
+            for (pos, _) in prefab.entities.iter() {
+                let center_x = (pos.x * IMAGE_TILE_SIZE as f32) as u32;
+                let center_y = (pos.y * IMAGE_TILE_SIZE as f32) as u32;
+                for y in center_y - 1..center_y + 1 {
+                    for x in center_x - 1..center_x + 1 {
+                        image.put_pixel(x, y, Pixel::from_channels(255, 0, 0, 255));
+                    }
+                }
+            }
+
+            let name: String = prefab
+                .name
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                .collect();
+            image
+                .save(format!("{}/{}_{}.png", dir, i, name))
+                .map_err(|_| "Couldn't save prefab image")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// If something can walk on the tile, when the tile is opened.
+fn is_passable(tile: &GenTile) -> bool {
+    tile.kind.is_passable() || matches!(&tile.change_if, Some((kind, _)) if kind.is_passable())
+}
+
+fn tile_kind(kind: &GenTileKind) -> TileKind {
+    match kind {
+        GenTileKind::Floor => TileKind::Floor,
+        GenTileKind::Wall => TileKind::Wall,
+        GenTileKind::Window => TileKind::Window,
+        GenTileKind::Door => TileKind::Door(false),
+    }
+}
+
+/// The characters the tile is written as in the prefab file.
+/// Tiles from the legend are written as the built in tile they're the same as.
+fn tile_code(tile: &GenTile) -> String {
+    match &tile.change_if {
+        Some((kind, direction)) => {
+            let first = match kind {
+                GenTileKind::Door => 'D',
+                _ => '#',
+            };
+            let second = match direction {
+                Direction::Left => '<',
+                Direction::Right => '>',
+                Direction::Up => '^',
+                Direction::Down => 'v',
+            };
+            format!("{}{}", first, second)
+        }
+        None => match tile.kind {
+            GenTileKind::Floor => "..",
+            GenTileKind::Wall => "##",
+            GenTileKind::Window => "oo",
+            GenTileKind::Door => "DD",
+        }
+        .to_string(),
+    }
+}
@@ -0,0 +1,176 @@
+use super::entity::EvilEnemy;
+use super::{AnimationState, SoundEvent, TileKind, World, ALERT_DISTANCE};
+use crate::audio::Sound;
+use crate::Vec2;
+
+/// Moves entities by their velocity, bounces them off walls, and moves their sprites along.
+/// Entities that can open doors open the ones they walk into.
+pub(super) fn move_entities(world: &mut World, time_step: f32, world_time: f32) {
+    let entities = &world.entities.entities;
+    for (entity_id, transform) in world.entities.transforms.iter_mut() {
+        transform.pos.x += transform.vel.x * time_step;
+        if world
+            .tiles
+            .square_is_colliding(transform.pos, transform.size)
+        {
+            transform.pos.x -= transform.vel.x * time_step;
+            transform.vel.x *= -1.0;
+        }
+
+        transform.pos.y += transform.vel.y * time_step;
+        if world
+            .tiles
+            .square_is_colliding(transform.pos, transform.size)
+        {
+            transform.pos.y -= transform.vel.y * time_step;
+            transform.vel.y *= -1.0;
+        }
+
+        transform.vel -= transform.vel * transform.drag * time_step;
+
+        if let Some(sprite_id) = transform.sprite {
+            let sprite = world.sprites.get_mut(sprite_id).unwrap();
+            sprite.rot = transform.rot;
+            world.tiles.move_sprite(sprite_id, sprite, transform.pos);
+        }
+
+        let can_open_doors =
+            matches!(entities.get(entity_id), Some(entity) if entity.can_open_doors);
+        let (x, y) = (
+            transform.pos.x.floor() as isize,
+            transform.pos.y.floor() as isize,
+        );
+        if let Some(tile) = world.tiles.get_mut(x, y) {
+            if can_open_doors && matches!(tile.kind(), TileKind::Door(false)) {
+                tile.set_kind_with_time(TileKind::Door(true), world_time);
+                world.sound_events.push(SoundEvent {
+                    sound: Sound::Door,
+                    pos: Vec2::new(x as f32 + 0.5, y as f32 + 0.5),
+                });
+            }
+        }
+    }
+}
+
+pub(super) fn play_footsteps(world: &mut World, time_step: f32, _world_time: f32) {
+    for (_, footsteps, transform) in world
+        .entities
+        .footsteps
+        .join_mut(&world.entities.transforms)
+    {
+        if transform.vel.magnitude() > 0.5 {
+            footsteps.timer -= time_step;
+            if footsteps.timer <= 0.0 {
+                footsteps.timer += footsteps.interval;
+                world.sound_events.push(SoundEvent {
+                    sound: Sound::Footstep,
+                    pos: transform.pos,
+                });
+            }
+        } else {
+            footsteps.timer = 0.0;
+        }
+    }
+}
+
+pub(super) fn update_evil_enemies(world: &mut World, time_step: f32, _world_time: f32) {
+    for (entity_id, evil_enemy) in world.entities.evil_enemies.iter_mut() {
+        match evil_enemy {
+            EvilEnemy::Wander(time) => {
+                *time -= time_step;
+
+                if *time < 0.0 {
+                    world
+                        .entities
+                        .transforms
+                        .get_mut(entity_id)
+                        .expect("Evil enemy needs a transform")
+                        .vel += Vec2::new(
+                        world.random.get_float() - 0.5,
+                        world.random.get_float() - 0.5,
+                    );
+                    *time = world.random.get_float() * 3.0 + 1.0;
+                }
+
+                // Notice anything with health that is close by and can be seen.
+                let transforms = &world.entities.transforms;
+                let tiles = &world.tiles;
+                let pos = transforms
+                    .get(entity_id)
+                    .expect("Evil enemy needs a transform")
+                    .pos;
+                let target = world.entities.healths.iter().find_map(|(target, _)| {
+                    let target_pos = transforms.get(target)?.pos;
+                    if target != entity_id
+                        && (target_pos - pos).magnitude() < ALERT_DISTANCE
+                        && tiles.walls_between(pos, target_pos) == 0
+                    {
+                        Some(target)
+                    } else {
+                        None
+                    }
+                });
+                if let Some(target) = target {
+                    *evil_enemy = EvilEnemy::Angry(target);
+                    world.sound_events.push(SoundEvent {
+                        sound: Sound::Alert,
+                        pos,
+                    });
+                }
+            }
+            EvilEnemy::Angry(target) => match world.entities.transforms.get(*target) {
+                Some(target_transform) => {
+                    let target_pos = target_transform.pos;
+                    let evil_enemy_transform = world
+                        .entities
+                        .transforms
+                        .get_mut(entity_id)
+                        .expect("Evil enemy needs a transform");
+
+                    evil_enemy_transform.vel +=
+                        (target_pos - evil_enemy_transform.pos) * time_step * 0.1;
+                }
+                None => {
+                    *evil_enemy = EvilEnemy::Wander(2.0);
+                }
+            },
+        }
+
+        // They look where they're going, which picks the side of their sprite that is drawn.
+        let transform = world
+            .entities
+            .transforms
+            .get_mut(entity_id)
+            .expect("Evil enemy needs a transform");
+        if transform.vel.magnitude() > 0.1 {
+            transform.rot = (-transform.vel.x).atan2(transform.vel.y);
+        }
+    }
+}
+
+/// Switches the animations of sprites to match what their entities are doing.
+pub(super) fn update_animators(world: &mut World, _time_step: f32, world_time: f32) {
+    let transforms = &world.entities.transforms;
+    for (entity_id, animator, transform) in world.entities.animators.join_mut(transforms) {
+        let state = match world.entities.evil_enemies.get(entity_id) {
+            Some(EvilEnemy::Angry(target))
+                if matches!(
+                    transforms.get(*target),
+                    Some(target) if (target.pos - transform.pos).magnitude() < 1.0
+                ) =>
+            {
+                AnimationState::Attack
+            }
+            _ if transform.vel.magnitude() > 0.1 => AnimationState::Walk,
+            _ => AnimationState::Idle,
+        };
+
+        if state != animator.state {
+            animator.state = state;
+            if let Some(sprite_id) = transform.sprite {
+                world.sprites.get_mut(sprite_id).unwrap().animation =
+                    animator.animation(world_time);
+            }
+        }
+    }
+}
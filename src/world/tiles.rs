@@ -1,4 +1,5 @@
-use super::{Sprite, SpriteId};
+use super::{ExploredTiles, Sprite, SpriteId};
+use crate::raycast::{raycast, Raycast};
 use crate::texture::{Animation, Texture};
 use crate::Vec2;
 
@@ -6,6 +7,12 @@ pub struct TileMap {
     tiles: Vec<Tile>,
     width: usize,
     height: usize,
+    /// The lists of tiles that sprites have moved out of, which are given to the tiles
+    /// sprites move into, so that moving sprites around doesn't have to allocate.
+    spare_sprite_lists: Vec<Vec<SpriteId>>,
+    /// How many sprite lists there are in the tiles and the spare ones together,
+    /// which `spare_sprite_lists` always has room for.
+    n_sprite_lists: usize,
 }
 
 impl TileMap {
@@ -16,10 +23,38 @@ impl TileMap {
             tiles,
             width,
             height,
+            spare_sprite_lists: Vec::new(),
+            n_sprite_lists: 0,
         }
     }
 
-    pub fn to_image(&self, file: &str) {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// A hash of the kinds of all the tiles, that tells different maps of the same size apart.
+    /// Doors count the same whether they're open or not.
+    pub fn layout_hash(&self) -> u64 {
+        self.tiles
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, tile| {
+                let kind = match tile.kind {
+                    TileKind::Floor => 0,
+                    TileKind::Wall => 1,
+                    TileKind::Window => 2,
+                    TileKind::Door(_) => 3,
+                };
+                (hash ^ kind).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    /// Saves the map as an image. If the explored tiles are given,
+    /// the tiles that haven't been explored are darker.
+    pub fn to_image(&self, file: &str, explored: Option<&ExploredTiles>) {
         use image::{ImageBuffer, Pixel, Rgba};
 
         let mut image =
@@ -27,14 +62,19 @@ impl TileMap {
 
         for y in 0..self.width {
             for x in 0..self.height {
-                let (r, g, b, a) = match self.get(x as isize, y as isize).unwrap().kind {
-                    TileKind::Floor => (255, 200, 200, 255),
-                    TileKind::Wall => (50, 50, 50, 255),
-                    TileKind::Window => (50, 50, 75, 255),
-                    TileKind::Door(_) => (100, 200, 200, 255),
+                let [mut r, mut g, mut b] =
+                    self.get(x as isize, y as isize).unwrap().kind.map_color();
+                let is_explored = match explored {
+                    Some(explored) => explored.is_explored(x as isize, y as isize),
+                    None => true,
                 };
+                if !is_explored {
+                    r /= 4;
+                    g /= 4;
+                    b /= 4;
+                }
 
-                let pixel = Pixel::from_channels(r, g, b, a);
+                let pixel = Pixel::from_channels(r, g, b, 255);
                 image.put_pixel(x as u32, y as u32, pixel);
             }
         }
@@ -42,6 +82,15 @@ impl TileMap {
         image.save(file).unwrap();
     }
 
+    /// Adds a sprite that never moves to all the tiles it's in.
+    pub fn insert_static_sprite(&mut self, sprite: StaticSprite) {
+        for (x, y) in tiles_in_square(sprite.pos, sprite.size * 0.5) {
+            if let Some(tile) = self.get_mut(x, y) {
+                tile.static_sprites.push(sprite);
+            }
+        }
+    }
+
     pub fn move_sprite(&mut self, sprite_id: SpriteId, sprite: &mut Sprite, new_pos: Vec2) {
         let size = sprite.size;
 
@@ -70,6 +119,10 @@ impl TileMap {
                 if let Some(tile) = self.get_mut(x, y) {
                     if let Some(loc) = tile.sprites_inside.iter().position(|&v| v == sprite_id) {
                         tile.sprites_inside.swap_remove(loc);
+                        if tile.sprites_inside.is_empty() {
+                            let list = std::mem::take(&mut tile.sprites_inside);
+                            self.spare_sprite_lists.push(list);
+                        }
                     }
                 }
             }
@@ -77,10 +130,43 @@ impl TileMap {
 
         for y in new_top..=new_bottom {
             for x in new_left..=new_right {
-                if let Some(tile) = self.get_mut(x, y) {
-                    tile.sprites_inside.push(sprite_id);
+                self.push_sprite_id(x, y, sprite_id);
+            }
+        }
+    }
+
+    /// Adds a new sprite to the tiles it covers.
+    pub fn insert_sprite(&mut self, sprite_id: SpriteId, pos: Vec2, size: f32) {
+        // Keep enough spare lists around for every tile the sprite could cover, with room
+        // for a few sprites each, so that the lists are already there when it moves.
+        let max_tiles = (size.ceil() as usize + 1).pow(2);
+        self.n_sprite_lists += max_tiles;
+        self.spare_sprite_lists
+            .reserve(self.n_sprite_lists - self.spare_sprite_lists.len());
+        self.spare_sprite_lists
+            .extend((0..max_tiles).map(|_| Vec::with_capacity(4)));
+
+        for y in (pos.y - size / 2.0).floor() as isize..=(pos.y + size / 2.0).floor() as isize {
+            for x in (pos.x - size / 2.0).floor() as isize..=(pos.x + size / 2.0).floor() as isize {
+                self.push_sprite_id(x, y, sprite_id);
+            }
+        }
+    }
+
+    /// Adds a sprite to the list of a tile, which gets a spare list if it doesn't have one.
+    fn push_sprite_id(&mut self, x: isize, y: isize, sprite_id: SpriteId) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            let tile = &mut self.tiles[y as usize * self.width + x as usize];
+            if tile.sprites_inside.capacity() == 0 {
+                match self.spare_sprite_lists.pop() {
+                    Some(list) => tile.sprites_inside = list,
+                    None => {
+                        self.n_sprite_lists += 1;
+                        self.spare_sprite_lists.reserve(self.n_sprite_lists);
+                    }
                 }
             }
+            tile.sprites_inside.push(sprite_id);
         }
     }
 
@@ -88,6 +174,33 @@ impl TileMap {
         tiles_in_square(pos, size).any(|(x, y)| self.tile_is_colliding(x, y))
     }
 
+    /// The number of solid tiles on a straight line between two points.
+    pub fn walls_between(&self, from: Vec2, to: Vec2) -> usize {
+        let distance = (to - from).magnitude();
+        if distance < 0.0001 {
+            return 0;
+        }
+
+        let direction = (to - from) / distance;
+        let mut n_walls = 0;
+        raycast(
+            Raycast {
+                x: from.x,
+                y: from.y,
+                dx: direction.x,
+                dy: direction.y,
+                max_distance: distance,
+            },
+            |dist, x, y, _, _, _| {
+                if dist < distance && self.tile_is_colliding(x, y) {
+                    n_walls += 1;
+                }
+                true
+            },
+        );
+        n_walls
+    }
+
     pub fn tile_is_colliding(&self, x: isize, y: isize) -> bool {
         if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
             self.tiles[y as usize * self.width + x as usize].is_solid()
@@ -135,9 +248,29 @@ pub struct Tile {
     graphics: Option<TileGraphics>,
     kind: TileKind,
     pub sprites_inside: Vec<SpriteId>,
+    /// Sprites that never move, like decorations. Unlike the other sprites they are stored
+    /// right in the tiles they're in, so the renderer doesn't have to look them up.
+    pub static_sprites: Vec<StaticSprite>,
     pub floor_gfx: Texture,
 }
 
+/// A sprite that never moves or turns, so it looks the same from every side.
+#[derive(Clone, Copy)]
+pub struct StaticSprite {
+    pub animation: Animation,
+    pub pos: Vec2,
+    pub size: f32,
+    pub y_pos: f32,
+}
+
+impl StaticSprite {
+    /// The tile that its position is in. It's in the sprites of this tile even if it sticks
+    /// into other tiles as well.
+    pub fn home_tile(&self) -> (isize, isize) {
+        (self.pos.x.floor() as isize, self.pos.y.floor() as isize)
+    }
+}
+
 #[derive(Clone)]
 pub enum TileKind {
     Floor,
@@ -146,6 +279,18 @@ pub enum TileKind {
     Door(bool),
 }
 
+impl TileKind {
+    /// The color of the tile on maps.
+    pub fn map_color(&self) -> [u8; 3] {
+        match self {
+            TileKind::Floor => [255, 200, 200],
+            TileKind::Wall => [50, 50, 50],
+            TileKind::Window => [50, 50, 75],
+            TileKind::Door(_) => [100, 200, 200],
+        }
+    }
+}
+
 impl Tile {
     pub fn new(kind: TileKind) -> Self {
         Self::new_with_time(kind, 0.0)
@@ -157,12 +302,12 @@ impl Tile {
             kind: TileKind::Floor,
             floor_gfx: Texture::Floor,
             sprites_inside: Vec::new(),
+            static_sprites: Vec::new(),
         };
         tile.set_kind_with_time(kind, time);
         tile
     }
 
-    #[allow(unused)]
     pub fn kind(&self) -> &TileKind {
         &self.kind
     }